@@ -0,0 +1,341 @@
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use log::info;
+use sha2::{Digest, Sha256};
+use tempfile::NamedTempFile;
+
+use crate::error::{Error, Result};
+
+/// Environment variable overriding the cache root directory.
+pub const CACHE_DIR_ENV: &str = "LLMS_FROM_SCRATCH_CACHE_DIR";
+
+/// Environment variable that, when set to anything but `0` or `false`, forbids downloads.
+pub const OFFLINE_ENV: &str = "LLMS_FROM_SCRATCH_OFFLINE";
+
+/// A remote file the crate knows how to download, optionally pinned to a SHA-256 digest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Artifact<'a> {
+    pub url: &'a str,
+    /// Lowercase hex SHA-256 of the file's contents.
+    pub sha256: Option<&'a str>,
+}
+
+impl<'a> Artifact<'a> {
+    pub const fn unpinned(url: &'a str) -> Self {
+        Self { url, sha256: None }
+    }
+
+    pub const fn pinned(url: &'a str, sha256: &'a str) -> Self {
+        Self {
+            url,
+            sha256: Some(sha256),
+        }
+    }
+}
+
+/// A content-addressed store for downloaded corpora and weights.
+///
+/// Pinned artifacts live under `sha256/<digest>` and are re-verified every time they're handed
+/// out, so a truncated or tampered file is caught rather than silently reused. Unpinned artifacts
+/// are keyed by the digest of their URL under `url/`. Downloads are written to a temporary file
+/// next to their final location and renamed into place only once complete.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cache {
+    root: PathBuf,
+    offline: bool,
+}
+
+impl Cache {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            offline: false,
+        }
+    }
+
+    /// Configures the cache from [`CACHE_DIR_ENV`] and [`OFFLINE_ENV`], defaulting to the
+    /// platform cache directory.
+    pub fn from_env() -> Result<Self> {
+        let root = match env::var_os(CACHE_DIR_ENV) {
+            Some(dir) => PathBuf::from(dir),
+            None => dirs::cache_dir()
+                .ok_or_else(|| {
+                    Error::Config(format!(
+                        "no cache directory for this platform, set {CACHE_DIR_ENV}"
+                    ))
+                })?
+                .join("llms-from-scratch-burn"),
+        };
+
+        let offline =
+            env::var(OFFLINE_ENV).is_ok_and(|v| !matches!(v.as_str(), "" | "0" | "false"));
+
+        Ok(Self::new(root).offline(offline))
+    }
+
+    /// In offline mode, [`Cache::get`] errors instead of downloading a missing artifact.
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Where `artifact` is stored, whether or not it has been downloaded yet.
+    pub fn path(&self, artifact: &Artifact) -> PathBuf {
+        match artifact.sha256 {
+            Some(digest) => self.root.join("sha256").join(digest.to_lowercase()),
+            None => self
+                .root
+                .join("url")
+                .join(hex_digest(artifact.url.as_bytes())),
+        }
+    }
+
+    /// Returns the local path of `artifact`, downloading it first if necessary.
+    pub fn get(&self, artifact: &Artifact) -> Result<PathBuf> {
+        let path = self.path(artifact);
+
+        if path.exists() {
+            if let Some(expected) = artifact.sha256 {
+                verify(&path, expected)?;
+            }
+            return Ok(path);
+        }
+
+        if self.offline {
+            return Err(Error::NotCached {
+                url: artifact.url.to_string(),
+                path,
+            });
+        }
+
+        self.download(artifact, &path)?;
+
+        Ok(path)
+    }
+
+    pub fn read_to_string(&self, artifact: &Artifact) -> Result<String> {
+        let path = self.get(artifact)?;
+        fs::read_to_string(&path).map_err(|source| Error::File { path, source })
+    }
+
+    fn download(&self, artifact: &Artifact, path: &Path) -> Result<()> {
+        let dir = path.parent().expect("cache paths always have a parent");
+        fs::create_dir_all(dir)?;
+
+        let client = reqwest::blocking::Client::new();
+        let mut res = client.get(artifact.url).send()?.error_for_status()?;
+        let expected_len = res.content_length();
+
+        let mut file = NamedTempFile::new_in(dir)?;
+        let mut writer = HashingWriter::new(&mut file);
+        let len = io::copy(&mut res, &mut writer)?;
+        let digest = writer.finish();
+
+        if let Some(expected_len) = expected_len
+            && len != expected_len
+        {
+            return Err(Error::Truncated {
+                url: artifact.url.to_string(),
+                expected: expected_len,
+                actual: len,
+            });
+        }
+
+        if let Some(expected) = artifact.sha256
+            && !digest.eq_ignore_ascii_case(expected)
+        {
+            return Err(checksum_mismatch(artifact.url, expected, digest));
+        }
+
+        file.as_file().sync_all()?;
+        file.persist(path).map_err(|e| e.error)?;
+
+        info!(url = artifact.url, path:? = path, len; "cached artifact");
+
+        Ok(())
+    }
+}
+
+fn hex_digest(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Checks that the file at `path` hashes to the lowercase hex SHA-256 `expected`.
+pub(crate) fn verify(path: &Path, expected: &str) -> Result<()> {
+    let mut writer = HashingWriter::new(io::sink());
+    io::copy(&mut File::open(path)?, &mut writer)?;
+    let digest = writer.finish();
+
+    if !digest.eq_ignore_ascii_case(expected) {
+        return Err(checksum_mismatch(
+            &path.display().to_string(),
+            expected,
+            digest,
+        ));
+    }
+
+    Ok(())
+}
+
+fn checksum_mismatch(what: &str, expected: &str, actual: String) -> Error {
+    Error::ChecksumMismatch {
+        what: what.to_string(),
+        expected: expected.to_string(),
+        actual,
+    }
+}
+
+/// Hashes everything written through it.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    fn finish(self) -> String {
+        format!("{:x}", self.hasher.finalize())
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use tempfile::TempDir;
+
+    use crate::{
+        cache::{Artifact, Cache, hex_digest},
+        error::Error,
+    };
+
+    /// Serves a single HTTP response whose `Content-Length` is `declared_len`, then hangs up.
+    fn serve_once(body: &'static [u8], declared_len: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            _ = stream.read(&mut request).unwrap();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {declared_len}\r\nConnection: close\r\n\r\n"
+            )
+            .unwrap();
+            stream.write_all(body).unwrap();
+        });
+
+        format!("http://{addr}/corpus.txt")
+    }
+
+    #[test]
+    fn test_download_pinned_artifact() {
+        let root = TempDir::new().unwrap();
+        let body = b"I HAD always thought Jack Gisburn rather a cheap genius";
+        let digest = hex_digest(body);
+        let url = serve_once(body, body.len());
+
+        let cache = Cache::new(root.path());
+        let artifact = Artifact::pinned(&url, &digest);
+        let path = cache.get(&artifact).unwrap();
+
+        assert_eq!(path, root.path().join("sha256").join(&digest));
+        assert_eq!(fs::read(&path).unwrap(), body);
+
+        // NB: The server only answers once, so this has to come from the cache.
+        assert_eq!(cache.offline(true).get(&artifact).unwrap(), path);
+    }
+
+    #[test]
+    fn test_download_checksum_mismatch() {
+        let root = TempDir::new().unwrap();
+        let url = serve_once(b"tampered", 8);
+
+        let cache = Cache::new(root.path());
+        let digest = hex_digest(b"original");
+        let artifact = Artifact::pinned(&url, &digest);
+
+        assert!(matches!(
+            cache.get(&artifact),
+            Err(Error::ChecksumMismatch { .. })
+        ));
+        assert!(!cache.path(&artifact).exists());
+    }
+
+    #[test]
+    fn test_download_truncated() {
+        let root = TempDir::new().unwrap();
+        let url = serve_once(b"short", 1024);
+
+        let cache = Cache::new(root.path());
+        let artifact = Artifact::unpinned(&url);
+
+        // NB: Depending on timing, the short body surfaces either as our own length check or as
+        // reqwest noticing the connection closed early.
+        assert!(matches!(
+            cache.get(&artifact),
+            Err(Error::Truncated { .. } | Error::Io(_) | Error::Http(_))
+        ));
+        assert!(!cache.path(&artifact).exists());
+    }
+
+    #[test]
+    fn test_cached_file_tampered() {
+        let root = TempDir::new().unwrap();
+        let cache = Cache::new(root.path()).offline(true);
+        let artifact = Artifact::pinned("https://example.com/weights.bin", "00");
+
+        let path = cache.path(&artifact);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, b"not what was pinned").unwrap();
+
+        assert!(matches!(
+            cache.get(&artifact),
+            Err(Error::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_offline_missing() {
+        let root = TempDir::new().unwrap();
+        let cache = Cache::new(root.path()).offline(true);
+
+        assert!(matches!(
+            cache.get(&Artifact::unpinned("https://example.com/the-verdict.txt")),
+            Err(Error::NotCached { .. })
+        ));
+    }
+}
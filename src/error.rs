@@ -0,0 +1,96 @@
+use std::{io, path::PathBuf, string::FromUtf8Error};
+
+/// Everything that can go wrong in this crate outside of a listing's `main`.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unknown token {0:?}")]
+    UnknownToken(String),
+
+    #[error("special token {0:?} isn't allowed in the input")]
+    DisallowedSpecialToken(String),
+
+    #[error("unknown token id {0}")]
+    UnknownId(usize),
+
+    #[error("decoded bytes are not valid UTF-8: {0}")]
+    InvalidUtf8(#[from] FromUtf8Error),
+
+    #[error("invalid tokenizer: {0}")]
+    InvalidTokenizer(String),
+
+    #[error("tiktoken: {0}")]
+    Tiktoken(String),
+
+    #[error("invalid pattern: {0}")]
+    InvalidPattern(String),
+
+    #[error("invalid corpus source {0:?}, expected one of: bundled, cache:<dir>, fetch")]
+    InvalidCorpusSource(String),
+
+    #[error("{location}: {reason}")]
+    InvalidDocument { location: String, reason: String },
+
+    #[error("checksum mismatch for {what}: expected sha256 {expected}, got {actual}")]
+    ChecksumMismatch {
+        what: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("{url} was truncated: expected {expected} bytes, got {actual}")]
+    Truncated {
+        url: String,
+        expected: u64,
+        actual: u64,
+    },
+
+    #[error("{url} is not cached at {} and the cache is offline", path.display())]
+    NotCached { url: String, path: PathBuf },
+
+    #[error("{0}")]
+    Config(String),
+
+    #[error("max_length {max_length} exceeds the model's context length {context_length}")]
+    ContextTooLong {
+        max_length: usize,
+        context_length: usize,
+    },
+
+    #[error("corpus encodes to {tokens} tokens, but at least {required} are needed")]
+    CorpusTooSmall { tokens: usize, required: usize },
+
+    #[error("invalid token shard {}: {reason}", path.display())]
+    InvalidShard { path: PathBuf, reason: String },
+
+    #[error("{}: {source}", path.display())]
+    File { path: PathBuf, source: io::Error },
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    GlobPattern(#[from] glob::PatternError),
+
+    #[error(transparent)]
+    Glob(#[from] glob::GlobError),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl From<regex::Error> for Error {
+    fn from(e: regex::Error) -> Self {
+        Self::InvalidPattern(e.to_string())
+    }
+}
+
+impl From<fancy_regex::Error> for Error {
+    fn from(e: fancy_regex::Error) -> Self {
+        Self::InvalidPattern(e.to_string())
+    }
+}
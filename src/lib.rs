@@ -1,5 +1,7 @@
 use std::error::Error;
 
+pub mod cache;
+pub mod error;
 pub mod listings;
 
 pub trait Listing: Send + Sync {
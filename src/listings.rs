@@ -1,2 +1,3 @@
 pub mod ch02;
 pub mod ch03;
+pub mod ch04;
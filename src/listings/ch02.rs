@@ -1,27 +1,51 @@
 #![allow(dead_code)]
 use std::{
     collections::{HashMap, HashSet},
-    error::Error,
-    fs::{self, File},
-    io,
-    sync::Arc,
+    iter,
+    ops::Range,
+    path::Path,
+    sync::{Arc, LazyLock},
 };
 
 use burn::{
     Tensor,
     data::{
-        dataloader::{DataLoader, DataLoaderBuilder, batcher::Batcher},
+        dataloader::{DataLoader, batcher::Batcher},
         dataset::{Dataset, InMemDataset},
     },
     prelude::Backend,
-    tensor::{Int, TensorData},
+    tensor::{Bool, Int, TensorData},
 };
 use log::info;
+use rayon::prelude::*;
 use regex::Regex;
-use tempfile::NamedTempFile;
 
-use crate::{Listing, listings::ch02::tokenizers::UnsafeBPETokenizer};
+use crate::{
+    Listing,
+    error::{Error, Result},
+    listings::{
+        ch02::{
+            corpus::{Corpus, DOCUMENT_SEPARATOR, Document},
+            order::{DataOrder, ResumableDataLoader},
+            sources::CorpusSource,
+            splits::{SplitBy, SplitConfig, Splits},
+            streaming::{StreamingDataLoader, StreamingDataset},
+            tokenizers::{
+                Encoding, SpecialTokenPolicy, TiktokenTokenizer,
+                serialization::{TokenizerFile, TokenizerModel},
+            },
+        },
+        ch04::GPTConfig,
+    },
+};
 
+pub mod corpus;
+pub mod order;
+pub mod shards;
+pub mod sources;
+pub mod splits;
+pub mod stats;
+pub mod streaming;
 pub mod tokenizers;
 
 pub struct L2_1;
@@ -29,7 +53,7 @@ pub struct L2_1;
 pub struct E2_1;
 
 impl Listing for E2_1 {
-    fn main(&self) -> Result<(), Box<dyn Error>> {
+    fn main(&self) -> Result<(), Box<dyn std::error::Error>> {
         let input = "Akwirw ier";
         let tokenizer = tiktoken_rs::get_bpe_from_model("gpt2").unwrap();
         let allowed_special: HashSet<&str> = HashSet::new();
@@ -42,28 +66,53 @@ impl Listing for E2_1 {
     }
 }
 
+const END_OF_TEXT: &str = "<|endoftext|>";
+const UNKNOWN: &str = "<|unk|>";
+
+/// The target id that losses skip, as in PyTorch's `cross_entropy` and the book.
+const IGNORE_INDEX: i64 = -100;
+
+static TOKENIZE_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"([,.:;?_!"()\']|--|\s)"#).unwrap());
+
+static DETOKENIZE_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"\s+([,.?!"()'])"#).unwrap());
+
+/// Roughly how much text [`Tokenizer::encode_parallel`] hands to each thread at a time.
+const ENCODE_CHUNK_BYTES: usize = 1 << 20;
+
+/// How many documents [`GPTDatasetV1::new_from_corpus`] reads before encoding them in parallel.
+const ENCODE_BATCH_DOCUMENTS: usize = 256;
+
 static THE_VERDICT_URL: &str = "https://raw.githubusercontent.com/rasbt/LLMs-from-scratch/main/ch02/01_main-chapter-code/the-verdict.txt";
 
 fn tokenize(s: &str) -> Vec<&str> {
-    let regex = Regex::new(r#"([,.:;?_!"()\']|--|\s)"#).unwrap();
+    tokenize_with_offsets(s)
+        .into_iter()
+        .map(|(_, token)| token)
+        .collect()
+}
+
+/// Like [`tokenize`], but also gives the byte offset each token starts at.
+fn tokenize_with_offsets(s: &str) -> Vec<(usize, &str)> {
     let mut result = Vec::new();
     let mut last_end = 0;
 
-    for mat in regex.find_iter(s) {
+    for mat in TOKENIZE_REGEX.find_iter(s) {
         if mat.start() > last_end {
-            result.push(&s[last_end..mat.start()]);
+            result.push((last_end, &s[last_end..mat.start()]));
         }
-        result.push(mat.as_str());
+        result.push((mat.start(), mat.as_str()));
         last_end = mat.end();
     }
 
     if last_end < s.len() {
-        result.push(&s[last_end..]);
+        result.push((last_end, &s[last_end..]));
     }
 
     result
         .into_iter()
-        .filter(|s| !s.trim_end().is_empty())
+        .filter(|(_, s)| !s.trim_end().is_empty())
         .collect()
 }
 
@@ -92,22 +141,15 @@ fn construct_vocab(corpus: &str, additional_tokens: Option<Vec<String>>) -> Hash
     )
 }
 
-fn text_from_url(url: String) -> Result<String, Box<dyn Error>> {
-    let client = reqwest::blocking::Client::new();
-
-    let mut res = client.get(url).send()?.error_for_status()?;
-
-    let mut file = NamedTempFile::new()?;
-
-    io::copy(&mut res, &mut file)?;
-
-    Ok(fs::read_to_string(file.path())?)
+/// Reads the corpus identified by `url` from whichever [`CorpusSource`] is configured.
+fn text_from_url(url: String) -> Result<String> {
+    CorpusSource::from_env()?.read_to_string(&url)
 }
 
 fn construct_vocab_from_url(
     corpus_url: String,
     additional_tokens: Option<Vec<String>>,
-) -> Result<HashMap<String, usize>, Box<dyn Error>> {
+) -> Result<HashMap<String, usize>> {
     let opened = text_from_url(corpus_url)?;
     info!(count = opened.chars().count(), excerpt:? = opened[0..99]; "file details");
 
@@ -115,19 +157,11 @@ fn construct_vocab_from_url(
 }
 
 impl Listing for L2_1 {
-    fn main(&self) -> Result<(), Box<dyn Error>> {
-        let client = reqwest::blocking::Client::new();
-
-        let path = "/tmp/the-verdict.txt";
+    fn main(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let source = CorpusSource::from_env()?;
 
-        let mut res = client.get(THE_VERDICT_URL).send()?.error_for_status()?;
-        let mut file = File::create(path)?;
-
-        info!(path; "downloaded file");
-
-        io::copy(&mut res, &mut file)?;
-
-        let opened = fs::read_to_string(path)?;
+        let opened = source.read_to_string(THE_VERDICT_URL)?;
+        info!(source:% = source; "loaded corpus");
         info!(count = opened.chars().count(), excerpt:? = opened[0..99]; "file details");
 
         let vocab = construct_vocab(&opened, None);
@@ -138,9 +172,162 @@ impl Listing for L2_1 {
     }
 }
 
-trait Tokenizer {
-    fn encode(&self, text: String) -> Vec<usize>;
-    fn decode(&self, ids: Vec<usize>) -> String;
+/// An encoded id and the part of the input it came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenSpan {
+    pub id: usize,
+    pub bytes: Range<usize>,
+    /// The characters overlapping `bytes`. Byte-level tokens can end or start partway through a
+    /// character, in which case neighbouring spans share it.
+    pub chars: Range<usize>,
+}
+
+impl TokenSpan {
+    /// Attaches character offsets to `(id, byte range)` pairs over `text`.
+    fn from_byte_ranges(
+        text: &str,
+        tokens: impl IntoIterator<Item = (usize, Range<usize>)>,
+    ) -> Vec<Self> {
+        let starts: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+
+        tokens
+            .into_iter()
+            .map(|(id, bytes)| {
+                let start = if bytes.start >= text.len() {
+                    starts.len()
+                } else {
+                    starts.partition_point(|s| *s <= bytes.start) - 1
+                };
+                let end = starts.partition_point(|s| *s < bytes.end);
+
+                Self {
+                    id,
+                    bytes,
+                    chars: start..end.max(start),
+                }
+            })
+            .collect()
+    }
+
+    /// Spans for byte-level tokens, which cover `text` back to back. The first `skip` bytes of the
+    /// tokens, such as a prefix space the tokenizer added itself, aren't part of `text`.
+    pub(crate) fn from_byte_lengths(
+        text: &str,
+        tokens: impl IntoIterator<Item = (usize, usize)>,
+        skip: usize,
+    ) -> Vec<Self> {
+        let mut end = 0;
+        let ranges: Vec<_> = tokens
+            .into_iter()
+            .map(|(id, len)| {
+                let start = end;
+                end += len;
+                let clamp = |offset: usize| offset.saturating_sub(skip).min(text.len());
+                (id, clamp(start)..clamp(end))
+            })
+            .collect();
+
+        Self::from_byte_ranges(text, ranges)
+    }
+}
+
+/// Splits `text` into chunks of at least `target` bytes that encode the same apart as together.
+///
+/// Chunks only break at a lone space between two non-whitespace characters. None of the
+/// pre-tokenizers in this crate let a token run past the end of a word there, and the space
+/// starts the next chunk just as it would start the next word's token.
+fn split_for_encoding(text: &str, target: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = text;
+
+    while rest.len() > target {
+        let start = rest.ceil_char_boundary(target);
+        let at = rest[start..]
+            .match_indices(' ')
+            .map(|(i, _)| start + i)
+            .find(|at| {
+                let before = rest[..*at].chars().next_back();
+                let after = rest[at + 1..].chars().next();
+                [before, after]
+                    .into_iter()
+                    .all(|c| c.is_some_and(|c| !c.is_whitespace()))
+            });
+        let Some(at) = at else {
+            break;
+        };
+
+        chunks.push(&rest[..at]);
+        rest = &rest[at..];
+    }
+
+    chunks.push(rest);
+    chunks
+}
+
+pub trait Tokenizer: Send + Sync {
+    /// Fails with [`Error::UnknownToken`] if `text` contains something outside the vocabulary
+    /// that the tokenizer has no fallback for.
+    fn encode(&self, text: String) -> Result<Vec<usize>>;
+
+    /// Encodes each of `texts` separately, across threads.
+    fn encode_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<usize>>> {
+        texts
+            .into_par_iter()
+            .map(|text| self.encode_parallel(&text))
+            .collect()
+    }
+
+    /// Same as [`Tokenizer::encode`], but splits large inputs into chunks that are encoded across
+    /// threads.
+    fn encode_parallel(&self, text: &str) -> Result<Vec<usize>> {
+        let chunks = split_for_encoding(text, ENCODE_CHUNK_BYTES);
+        if let [text] = chunks.as_slice() {
+            return self.encode(text.to_string());
+        }
+
+        let encoded = chunks
+            .into_par_iter()
+            .map(|chunk| self.encode(chunk.to_string()))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(encoded.concat())
+    }
+
+    /// Like [`Tokenizer::encode`], but also says which part of `text` each id came from.
+    ///
+    /// The default suits byte-level tokenizers, whose tokens' bytes concatenate back to `text`.
+    fn encode_with_offsets(&self, text: &str) -> Result<Vec<TokenSpan>> {
+        let tokens = self
+            .encode(text.to_string())?
+            .into_iter()
+            .map(|id| Ok((id, self.decode_bytes(id)?.len())))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(TokenSpan::from_byte_lengths(text, tokens, 0))
+    }
+
+    /// Fails with [`Error::UnknownId`] for ids outside the vocabulary, and with
+    /// [`Error::InvalidUtf8`] if the ids' bytes don't form valid UTF-8.
+    fn decode(&self, ids: Vec<usize>) -> Result<String>;
+
+    /// The raw bytes behind a single id, which for byte-level tokenizers needn't be valid UTF-8 on
+    /// their own.
+    fn decode_bytes(&self, id: usize) -> Result<Vec<u8>>;
+
+    /// One past the largest id the tokenizer can produce.
+    fn vocab_size(&self) -> usize;
+
+    /// The id that stands in for anything outside the vocabulary, for tokenizers that have one.
+    fn unknown_id(&self) -> Option<usize> {
+        None
+    }
+
+    /// Everything needed to rebuild this tokenizer with [`tokenizers::load_tokenizer`].
+    fn to_file(&self) -> TokenizerFile;
+
+    fn save(&self, path: &Path) -> Result<()> {
+        self.to_file().save(path)
+    }
 }
 
 struct SimpleTokenizerV1 {
@@ -158,19 +345,50 @@ impl SimpleTokenizerV1 {
 }
 
 impl Tokenizer for SimpleTokenizerV1 {
-    fn encode(&self, text: String) -> Vec<usize> {
+    fn encode(&self, text: String) -> Result<Vec<usize>> {
         tokenize(&text)
             .iter()
-            .map(|token| self.str_to_int[*token])
+            .map(|token| {
+                self.str_to_int
+                    .get(*token)
+                    .copied()
+                    .ok_or_else(|| Error::UnknownToken(token.to_string()))
+            })
             .collect()
     }
 
-    fn decode(&self, ids: Vec<usize>) -> String {
-        let tokens: Vec<String> = ids.iter().map(|id| self.int_to_str[id].clone()).collect();
-        let joined = tokens.join(" ");
-        let regex = Regex::new(r#"\s+([,.?!"()'])"#).unwrap();
+    fn encode_with_offsets(&self, text: &str) -> Result<Vec<TokenSpan>> {
+        let tokens = tokenize_with_offsets(text)
+            .into_iter()
+            .map(|(start, token)| {
+                let id = self
+                    .str_to_int
+                    .get(token)
+                    .copied()
+                    .ok_or_else(|| Error::UnknownToken(token.to_string()))?;
+                Ok((id, start..start + token.len()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(TokenSpan::from_byte_ranges(text, tokens))
+    }
+
+    fn decode(&self, ids: Vec<usize>) -> Result<String> {
+        decode_words(&self.int_to_str, ids)
+    }
+
+    fn decode_bytes(&self, id: usize) -> Result<Vec<u8>> {
+        decode_word_bytes(&self.int_to_str, id)
+    }
+
+    fn vocab_size(&self) -> usize {
+        self.int_to_str.len()
+    }
 
-        regex.replace_all(&joined, "$1").to_string()
+    fn to_file(&self) -> TokenizerFile {
+        TokenizerFile::new(TokenizerModel::SimpleV1 {
+            vocab: self.str_to_int.clone().into_iter().collect(),
+        })
     }
 }
 
@@ -180,107 +398,296 @@ struct SimpleTokenizerV2 {
 }
 
 impl SimpleTokenizerV2 {
-    fn new(corpus: Corpus) -> Self {
-        let additional_tokens = vec!["<|endoftext|>".into(), "<|unk|>".into()];
-        let vocab = corpus.to_vocab(additional_tokens.into());
-        Self {
+    fn new(corpus: Corpus) -> Result<Self> {
+        let additional_tokens = vec![END_OF_TEXT.into(), UNKNOWN.into()];
+        let vocab = corpus.to_vocab(additional_tokens.into())?;
+        Self::from_vocab(vocab)
+    }
+
+    /// Wraps a vocabulary that already contains `<|endoftext|>` and `<|unk|>`.
+    fn from_vocab(vocab: HashMap<String, usize>) -> Result<Self> {
+        for special in [END_OF_TEXT, UNKNOWN] {
+            if !vocab.contains_key(special) {
+                return Err(Error::InvalidTokenizer(format!(
+                    "vocab is missing special token {special:?}"
+                )));
+            }
+        }
+
+        Ok(Self {
             str_to_int: vocab.clone(),
             int_to_str: vocab.iter().map(|(k, v)| (*v, k.clone())).collect(),
-        }
+        })
     }
 }
 
 impl Tokenizer for SimpleTokenizerV2 {
-    fn encode(&self, text: String) -> Vec<usize> {
-        tokenize(&text)
+    fn encode(&self, text: String) -> Result<Vec<usize>> {
+        Ok(tokenize(&text)
             .iter()
             .map(|token| {
                 *(self
                     .str_to_int
                     .get(*token)
-                    .unwrap_or(&self.str_to_int["<|unk|>"]))
+                    .unwrap_or(&self.str_to_int[UNKNOWN]))
             })
-            .collect()
+            .collect())
+    }
+
+    fn encode_with_offsets(&self, text: &str) -> Result<Vec<TokenSpan>> {
+        let unknown = self.str_to_int[UNKNOWN];
+        let tokens = tokenize_with_offsets(text)
+            .into_iter()
+            .map(|(start, token)| {
+                let id = self.str_to_int.get(token).copied().unwrap_or(unknown);
+                (id, start..start + token.len())
+            });
+
+        Ok(TokenSpan::from_byte_ranges(text, tokens))
+    }
+
+    fn decode(&self, ids: Vec<usize>) -> Result<String> {
+        decode_words(&self.int_to_str, ids)
+    }
+
+    fn decode_bytes(&self, id: usize) -> Result<Vec<u8>> {
+        decode_word_bytes(&self.int_to_str, id)
     }
 
-    fn decode(&self, ids: Vec<usize>) -> String {
-        let tokens: Vec<String> = ids.iter().map(|id| self.int_to_str[id].clone()).collect();
-        let joined = tokens.join(" ");
-        let regex = Regex::new(r#"\s+([,.?!"()'])"#).unwrap();
+    fn vocab_size(&self) -> usize {
+        self.int_to_str.len()
+    }
+
+    fn unknown_id(&self) -> Option<usize> {
+        Some(self.str_to_int[UNKNOWN])
+    }
 
-        regex.replace_all(&joined, "$1").to_string()
+    fn to_file(&self) -> TokenizerFile {
+        TokenizerFile::new(TokenizerModel::SimpleV2 {
+            vocab: self.str_to_int.clone().into_iter().collect(),
+            special_tokens: [END_OF_TEXT, UNKNOWN]
+                .into_iter()
+                .map(|token| (token.to_string(), self.str_to_int[token]))
+                .collect(),
+        })
     }
 }
 
-enum Corpus {
-    Raw(String),
-    Url(String),
+/// Joins the words behind `ids`, dropping the space [`tokenize`] split off before punctuation.
+fn decode_words(int_to_str: &HashMap<usize, String>, ids: Vec<usize>) -> Result<String> {
+    let tokens = ids
+        .iter()
+        .map(|id| {
+            int_to_str
+                .get(id)
+                .map(String::as_str)
+                .ok_or(Error::UnknownId(*id))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let joined = tokens.join(" ");
+
+    Ok(DETOKENIZE_REGEX.replace_all(&joined, "$1").to_string())
 }
 
-impl Corpus {
-    fn to_vocab(&self, additional_tokens: Option<Vec<String>>) -> HashMap<String, usize> {
-        match self {
-            Corpus::Raw(text) => construct_vocab(text, additional_tokens),
-            Corpus::Url(url) => {
-                construct_vocab_from_url(url.to_string(), additional_tokens).unwrap()
-            }
-        }
-    }
+fn decode_word_bytes(int_to_str: &HashMap<usize, String>, id: usize) -> Result<Vec<u8>> {
+    int_to_str
+        .get(&id)
+        .map(|word| word.as_bytes().to_vec())
+        .ok_or(Error::UnknownId(id))
 }
 
 #[derive(Clone, Debug)]
-struct GPTDatasetItem<const N: usize> {
-    input_ids: [usize; N],
-    target_ids: [usize; N],
+struct GPTDatasetItem {
+    input_ids: Vec<usize>,
+    target_ids: Vec<usize>,
+    /// `false` where `input_ids` and `target_ids` are padding.
+    attention_mask: Vec<bool>,
+    /// Which document each of `input_ids` comes from, for datasets packed with document masks.
+    document_ids: Option<Vec<usize>>,
 }
 
 #[derive(Clone, Debug)]
 struct GPTDatasetBatch<B: Backend> {
     input_ids: Tensor<B, 2, Int>,
     target_ids: Tensor<B, 2, Int>,
+    /// `false` where the inputs are padding, whether from the item or the batch.
+    attention_mask: Tensor<B, 2, Bool>,
+    /// `[batch, query, key]`, `true` where the query and key positions are from the same
+    /// document. Only set when every item has document ids.
+    document_mask: Option<Tensor<B, 3, Bool>>,
+    /// Each input's position within its document, restarting from 0 at every document boundary.
+    /// Set along with `document_mask`.
+    position_ids: Option<Tensor<B, 2, Int>>,
 }
 
-#[derive(Clone, Debug)]
-struct GPTDatasetBatcher {}
+/// Pads every item in a batch out to the longest one. Items of the same length, such as the
+/// windows of a [`GPTDatasetV1`] made from text, are batched as they are.
+#[derive(Clone, Debug, Default)]
+struct GPTDatasetBatcher {
+    /// What inputs are padded with.
+    pad_id: usize,
+    /// What padded targets become, so that the loss skips them, as with the book's
+    /// [`IGNORE_INDEX`]. Without one, they're padded with `pad_id`, and targets an item has
+    /// already padded are left alone.
+    ignore_index: Option<i64>,
+}
+
+impl GPTDatasetBatcher {
+    fn new(pad_id: usize, ignore_index: Option<i64>) -> Self {
+        Self {
+            pad_id,
+            ignore_index,
+        }
+    }
+
+    fn pad<T: Clone>(row: &[T], len: usize, value: T) -> Vec<T> {
+        let mut row = row.to_vec();
+        row.resize(len, value);
+        row
+    }
+
+    fn stack<B: Backend>(rows: Vec<Vec<i64>>, device: &B::Device) -> Tensor<B, 2, Int> {
+        let tensors: Vec<Tensor<B, 2, Int>> = rows
+            .into_iter()
+            .map(|ids| {
+                let shape = [1, ids.len()];
+                TensorData::new(ids, shape).convert::<B::IntElem>()
+            })
+            .map(|data| Tensor::<B, 2, Int>::from_data(data, device))
+            .collect();
+
+        Tensor::cat(tensors, 0)
+    }
+
+    fn stack_mask<B: Backend>(rows: Vec<Vec<bool>>, device: &B::Device) -> Tensor<B, 2, Bool> {
+        let tensors: Vec<Tensor<B, 2, Bool>> = rows
+            .into_iter()
+            .map(|mask| {
+                let shape = [1, mask.len()];
+                TensorData::new(mask, shape)
+            })
+            .map(|data| Tensor::<B, 2, Bool>::from_data(data, device))
+            .collect();
+
+        Tensor::cat(tensors, 0)
+    }
 
-impl<B: Backend, const N: usize> Batcher<B, GPTDatasetItem<N>, GPTDatasetBatch<B>>
-    for GPTDatasetBatcher
-{
+    fn document_mask<B: Backend>(rows: &[Vec<usize>], device: &B::Device) -> Tensor<B, 3, Bool> {
+        let len = rows.first().map_or(0, |row| row.len());
+        let mask: Vec<bool> = rows
+            .iter()
+            .flat_map(|row| {
+                row.iter()
+                    .flat_map(move |query| row.iter().map(move |key| key == query))
+            })
+            .collect();
+
+        Tensor::from_data(TensorData::new(mask, [rows.len(), len, len]), device)
+    }
+
+    fn position_ids(row: &[usize]) -> Vec<i64> {
+        let mut position = 0;
+        row.iter()
+            .enumerate()
+            .map(|(i, document)| {
+                position = if i > 0 && row[i - 1] == *document {
+                    position + 1
+                } else {
+                    0
+                };
+                position
+            })
+            .collect()
+    }
+
+    fn targets(&self, item: &GPTDatasetItem, len: usize) -> Vec<i64> {
+        let padding = self.ignore_index.unwrap_or(self.pad_id as i64);
+        let mut targets: Vec<i64> = item
+            .target_ids
+            .iter()
+            .zip(&item.attention_mask)
+            .map(|(id, attend)| match self.ignore_index {
+                Some(ignore_index) if !attend => ignore_index,
+                _ => *id as i64,
+            })
+            .collect();
+        targets.resize(len, padding);
+        targets
+    }
+}
+
+impl<B: Backend> Batcher<B, GPTDatasetItem, GPTDatasetBatch<B>> for GPTDatasetBatcher {
     fn batch(
         &self,
-        items: Vec<GPTDatasetItem<N>>,
+        items: Vec<GPTDatasetItem>,
         device: &<B as Backend>::Device,
     ) -> GPTDatasetBatch<B> {
-        let input_tensors: Vec<Tensor<B, 2, Int>> = items
+        let len = items
             .iter()
-            .map(|item| TensorData::from(item.input_ids).convert::<B::IntElem>())
-            .map(|data| Tensor::<B, 1, Int>::from_data(data, device))
-            .map(|tensor| tensor.reshape([1, N]))
-            .collect();
+            .map(|item| item.input_ids.len())
+            .max()
+            .unwrap_or(0);
+
+        let input_ids = Self::stack(
+            items
+                .iter()
+                .map(|item| {
+                    let ids: Vec<i64> = item.input_ids.iter().map(|id| *id as i64).collect();
+                    Self::pad(&ids, len, self.pad_id as i64)
+                })
+                .collect(),
+            device,
+        );
+        let target_ids = Self::stack(
+            items.iter().map(|item| self.targets(item, len)).collect(),
+            device,
+        );
+        let attention_mask = Self::stack_mask(
+            items
+                .iter()
+                .map(|item| Self::pad(&item.attention_mask, len, false))
+                .collect(),
+            device,
+        );
 
-        let target_tensors: Vec<Tensor<B, 2, Int>> = items
+        // NB: padding is masked out anyway, so it joins the last document.
+        let document_ids = items
             .iter()
-            .map(|item| TensorData::from(item.target_ids).convert::<B::IntElem>())
-            .map(|data| Tensor::<B, 1, Int>::from_data(data, device))
-            .map(|tensor| tensor.reshape([1, N]))
-            .collect();
-
-        let input_ids = Tensor::cat(input_tensors, 0);
-        let target_ids = Tensor::cat(target_tensors, 0);
+            .map(|item| {
+                let ids = item.document_ids.as_deref()?;
+                Some(Self::pad(ids, len, ids.last().copied().unwrap_or(0)))
+            })
+            .collect::<Option<Vec<_>>>();
+        let (document_mask, position_ids) = match document_ids {
+            Some(rows) => (
+                Some(Self::document_mask(&rows, device)),
+                Some(Self::stack(
+                    rows.iter().map(|row| Self::position_ids(row)).collect(),
+                    device,
+                )),
+            ),
+            None => (None, None),
+        };
 
         GPTDatasetBatch {
             input_ids,
             target_ids,
+            attention_mask,
+            document_mask,
+            position_ids,
         }
     }
 }
 
-struct GPTDatasetV1<const N: usize> {
-    dataset: InMemDataset<GPTDatasetItem<N>>,
+struct GPTDatasetV1 {
+    dataset: InMemDataset<GPTDatasetItem>,
+    max_length: usize,
+    /// One past the largest id in the dataset.
+    min_vocab_size: usize,
 }
 
-impl<const N: usize> Dataset<GPTDatasetItem<N>> for GPTDatasetV1<N> {
-    fn get(&self, index: usize) -> Option<GPTDatasetItem<N>> {
+impl Dataset<GPTDatasetItem> for GPTDatasetV1 {
+    fn get(&self, index: usize) -> Option<GPTDatasetItem> {
         self.dataset.get(index)
     }
 
@@ -289,96 +696,528 @@ impl<const N: usize> Dataset<GPTDatasetItem<N>> for GPTDatasetV1<N> {
     }
 }
 
-impl<const N: usize> GPTDatasetV1<N> {
+impl GPTDatasetV1 {
     fn new_from_text(
         txt: String,
         tokenizer: Box<dyn Tokenizer>,
         max_length: usize,
         stride: usize,
-    ) -> Self {
-        let token_ids = tokenizer.encode(txt);
+        pad_id: Option<usize>,
+    ) -> Result<Self> {
+        Self::new_from_token_ids(tokenizer.encode_parallel(&txt)?, max_length, stride, pad_id)
+    }
+
+    /// Slides a `max_length` window over `token_ids` every `stride` ids. With a `pad_id`, the ids
+    /// left over after the last full window become one more item, padded out to `max_length` and
+    /// masked; without one they're dropped.
+    ///
+    /// Fails with [`Error::CorpusTooSmall`] if that leaves no items at all.
+    fn new_from_token_ids(
+        token_ids: Vec<usize>,
+        max_length: usize,
+        stride: usize,
+        pad_id: Option<usize>,
+    ) -> Result<Self> {
+        Self::new_from_windows(token_ids, None, max_length, stride, pad_id)
+    }
+
+    /// Packs `corpus`'s documents into one stream, each followed by a bare `<|endoftext|>` rather
+    /// than [`DOCUMENT_SEPARATOR`]. With `document_masks`, items also record which document each
+    /// position is from, so batches carry masks and positions that keep attention within a
+    /// document. An `<|endoftext|>` counts as part of the document it ends.
+    fn new_packed(
+        corpus: &Corpus,
+        tokenizer: Box<dyn Tokenizer>,
+        max_length: usize,
+        stride: usize,
+        document_masks: bool,
+    ) -> Result<Self> {
+        let mut token_ids = Vec::new();
+        let mut document_ids = Vec::new();
+        let mut document = 0;
+        encode_documents(
+            corpus.documents()?,
+            tokenizer.as_ref(),
+            END_OF_TEXT,
+            |separator, ids| {
+                if !separator.is_empty() {
+                    token_ids.extend_from_slice(separator);
+                    document_ids.extend(iter::repeat_n(document, separator.len()));
+                    document += 1;
+                }
+                token_ids.extend_from_slice(ids);
+                document_ids.extend(iter::repeat_n(document, ids.len()));
+                Ok(())
+            },
+        )?;
 
-        let mut input_ids: Vec<Vec<usize>> = Vec::new();
-        let mut target_ids: Vec<Vec<usize>> = Vec::new();
+        Self::new_from_windows(
+            token_ids,
+            document_masks.then_some(document_ids),
+            max_length,
+            stride,
+            None,
+        )
+    }
 
-        for i in (0..token_ids.len() - max_length).step_by(stride) {
-            let input_chunk = &token_ids[i..i + max_length];
-            let target_chunk = &token_ids[i + 1..=(i + max_length)];
+    fn new_from_windows(
+        token_ids: Vec<usize>,
+        document_ids: Option<Vec<usize>>,
+        max_length: usize,
+        stride: usize,
+        pad_id: Option<usize>,
+    ) -> Result<Self> {
+        if max_length == 0 || stride == 0 {
+            return Err(Error::Config(format!(
+                "max_length ({max_length}) and stride ({stride}) must both be positive"
+            )));
+        }
 
-            input_ids.push(input_chunk.to_vec());
-            target_ids.push(target_chunk.to_vec());
+        let required = if pad_id.is_some() { 2 } else { max_length + 1 };
+        if token_ids.len() < required {
+            return Err(Error::CorpusTooSmall {
+                tokens: token_ids.len(),
+                required,
+            });
         }
 
-        let zipped: Vec<_> = input_ids
-            .into_iter()
-            .zip(target_ids)
-            .map(|(input_ids, target_ids)| GPTDatasetItem {
-                input_ids: input_ids.try_into().unwrap(),
-                target_ids: target_ids.try_into().unwrap(),
+        let mut items: Vec<GPTDatasetItem> = (0..token_ids.len().saturating_sub(max_length))
+            .step_by(stride)
+            .map(|i| GPTDatasetItem {
+                input_ids: token_ids[i..i + max_length].to_vec(),
+                target_ids: token_ids[i + 1..=(i + max_length)].to_vec(),
+                attention_mask: vec![true; max_length],
+                document_ids: document_ids
+                    .as_ref()
+                    .map(|ids| ids[i..i + max_length].to_vec()),
             })
             .collect();
 
-        Self {
-            dataset: InMemDataset::new(zipped),
+        // NB: a window starting at `len - max_length` would need an id at `len` for its last
+        // target, so the ids after the last full window only make a shorter, padded one.
+        let start = items.len() * stride;
+        if let Some(pad_id) = pad_id
+            && start + 1 < token_ids.len()
+        {
+            let end = (start + max_length).min(token_ids.len() - 1);
+            let pad = |ids: &[usize], pad_id: usize| {
+                let mut ids = ids.to_vec();
+                ids.resize(max_length, pad_id);
+                ids
+            };
+
+            items.push(GPTDatasetItem {
+                input_ids: pad(&token_ids[start..end], pad_id),
+                target_ids: pad(&token_ids[start + 1..=end], pad_id),
+                attention_mask: (0..max_length).map(|i| i < end - start).collect(),
+                // NB: padding is masked out anyway, so it joins the last document.
+                document_ids: document_ids
+                    .as_ref()
+                    .map(|ids| pad(&ids[start..end], ids[end - 1])),
+            });
+        }
+
+        let max_id = token_ids.iter().chain(&pad_id).max();
+        Ok(Self {
+            dataset: InMemDataset::new(items),
+            max_length,
+            min_vocab_size: max_id.map_or(0, |id| id + 1),
+        })
+    }
+
+    /// Makes an item of each of `sequences`, predicting each id from the ones before it, for
+    /// variable-length data such as instructions. Sequences longer than `max_length + 1` ids are
+    /// truncated. Items aren't padded here, but by the [`GPTDatasetBatcher`].
+    fn new_from_sequences(sequences: Vec<Vec<usize>>, max_length: Option<usize>) -> Result<Self> {
+        if max_length == Some(0) {
+            return Err(Error::Config("max_length must be positive".to_string()));
+        }
+
+        let items = sequences
+            .into_iter()
+            .map(|mut ids| {
+                if ids.len() < 2 {
+                    return Err(Error::CorpusTooSmall {
+                        tokens: ids.len(),
+                        required: 2,
+                    });
+                }
+                if let Some(max_length) = max_length {
+                    ids.truncate(max_length + 1);
+                }
+
+                Ok(GPTDatasetItem {
+                    input_ids: ids[..ids.len() - 1].to_vec(),
+                    target_ids: ids[1..].to_vec(),
+                    attention_mask: vec![true; ids.len() - 1],
+                    document_ids: None,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let max_id = items
+            .iter()
+            .flat_map(|item| item.input_ids.iter().chain(item.target_ids.last()))
+            .max();
+        Ok(Self {
+            max_length: items
+                .iter()
+                .map(|item| item.input_ids.len())
+                .max()
+                .unwrap_or(0),
+            min_vocab_size: max_id.map_or(0, |id| id + 1),
+            dataset: InMemDataset::new(items),
+        })
+    }
+
+    /// Like [`GPTDatasetV1::new_from_text`], but reads the corpus a batch of documents at a time
+    /// rather than all into a single string first, and encodes each batch across threads.
+    fn new_from_corpus(
+        corpus: &Corpus,
+        tokenizer: Box<dyn Tokenizer>,
+        max_length: usize,
+        stride: usize,
+        pad_id: Option<usize>,
+    ) -> Result<Self> {
+        let mut token_ids = Vec::new();
+        encode_corpus(corpus, tokenizer.as_ref(), |ids| {
+            token_ids.extend_from_slice(ids);
+            Ok(())
+        })?;
+
+        Self::new_from_token_ids(token_ids, max_length, stride, pad_id)
+    }
+
+    /// Splits `corpus` per `split` before windowing each split on its own, so no window spans
+    /// two of them.
+    fn split(
+        corpus: &Corpus,
+        tokenizer: Box<dyn Tokenizer>,
+        max_length: usize,
+        stride: usize,
+        pad_id: Option<usize>,
+        split: &SplitConfig,
+    ) -> Result<Splits<Self>> {
+        split.validate()?;
+
+        let splits = match split.by {
+            SplitBy::Tokens => {
+                let mut token_ids = Vec::new();
+                encode_corpus(corpus, tokenizer.as_ref(), |ids| {
+                    token_ids.extend_from_slice(ids);
+                    Ok(())
+                })?;
+                split.cut(token_ids)
+            }
+            SplitBy::Documents => corpus.split_documents(split)?.try_map(|documents| {
+                let mut token_ids = Vec::new();
+                encode_documents(
+                    documents.into_iter().map(Ok),
+                    tokenizer.as_ref(),
+                    DOCUMENT_SEPARATOR,
+                    |separator, ids| {
+                        token_ids.extend_from_slice(separator);
+                        token_ids.extend_from_slice(ids);
+                        Ok(())
+                    },
+                )?;
+                Ok(token_ids)
+            })?,
+        };
+
+        splits.try_map(|token_ids| Self::new_from_token_ids(token_ids, max_length, stride, pad_id))
+    }
+
+    /// The most ids in any item's inputs and targets.
+    fn max_length(&self) -> usize {
+        self.max_length
+    }
+
+    /// Checks that a model built from `config` can take this dataset's items.
+    fn validate(&self, config: &GPTConfig) -> Result<()> {
+        validate_windows(self.max_length, self.min_vocab_size, config)
+    }
+}
+
+fn validate_windows(max_length: usize, min_vocab_size: usize, config: &GPTConfig) -> Result<()> {
+    config.validate()?;
+    if max_length > config.context_length {
+        return Err(Error::ContextTooLong {
+            max_length,
+            context_length: config.context_length,
+        });
+    }
+    if min_vocab_size > config.vocab_size {
+        return Err(Error::UnknownId(min_vocab_size - 1));
+    }
+
+    Ok(())
+}
+
+/// Encodes `corpus` a batch of documents at a time, across threads, and hands `f` each document's
+/// ids in order, preceded by the encoded [`DOCUMENT_SEPARATOR`] for all but the first.
+fn encode_corpus(
+    corpus: &Corpus,
+    tokenizer: &dyn Tokenizer,
+    mut f: impl FnMut(&[usize]) -> Result<()>,
+) -> Result<()> {
+    encode_documents(
+        corpus.documents()?,
+        tokenizer,
+        DOCUMENT_SEPARATOR,
+        |separator, ids| {
+            f(separator)?;
+            f(ids)
+        },
+    )
+}
+
+/// Hands `f` the encoded `separator` and then each document's ids. The separator is empty for the
+/// first document.
+fn encode_documents(
+    documents: impl Iterator<Item = Document>,
+    tokenizer: &dyn Tokenizer,
+    separator: &str,
+    mut f: impl FnMut(&[usize], &[usize]) -> Result<()>,
+) -> Result<()> {
+    let separator = tokenizer.encode(separator.to_string())?;
+
+    let mut first = true;
+    let mut documents = documents.peekable();
+    while documents.peek().is_some() {
+        let batch = documents
+            .by_ref()
+            .take(ENCODE_BATCH_DOCUMENTS)
+            .collect::<Result<Vec<_>>>()?;
+
+        for encoded in tokenizer.encode_batch(batch)? {
+            f(if first { &[] } else { &separator }, &encoded)?;
+            first = false;
         }
     }
+
+    Ok(())
 }
 
-fn create_dataloader_v1<B: Backend, const N: usize>(
+type GPTDataLoader<B> = Arc<dyn DataLoader<B, GPTDatasetBatch<B>>>;
+type StreamingGPTDataLoader<B> = Arc<dyn DataLoader<B, Result<GPTDatasetBatch<B>>>>;
+
+type ResumableGPTDataLoader<B> = Arc<ResumableDataLoader<B, GPTDatasetItem, GPTDatasetBatch<B>>>;
+
+/// Fails if `max_length` is longer than `config`'s context, the text encodes to ids outside its
+/// vocabulary, or the text is too short for a single window.
+///
+/// `order` takes the place of the book's `batch_size`, `shuffle` and `drop_last`, along with the
+/// shuffle seed and where to resume from. With `num_workers`, each batch's items are fetched
+/// across threads, without changing the order.
+fn create_dataloader_v1<B: Backend>(
     txt: String,
-    batch_size: usize,
+    config: &GPTConfig,
+    order: DataOrder,
     max_length: usize,
     stride: usize,
-    shuffle: bool,
-    _drop_last: bool,
     num_workers: usize,
-) -> Arc<dyn DataLoader<B, GPTDatasetBatch<B>>> {
-    let mut builder = DataLoaderBuilder::<B, _, _>::new(GPTDatasetBatcher {})
-        .batch_size(batch_size)
-        .num_workers(num_workers);
+    pad_id: Option<usize>,
+) -> Result<ResumableGPTDataLoader<B>> {
+    let dataset = GPTDatasetV1::new_from_text(
+        txt,
+        Box::new(TiktokenTokenizer::new(
+            Encoding::Gpt2,
+            SpecialTokenPolicy::allow([END_OF_TEXT]),
+        )?),
+        max_length,
+        stride,
+        pad_id,
+    )?;
+    dataset.validate(config)?;
+
+    dataloader(dataset, GPTDatasetBatcher::default(), order, num_workers)
+}
 
-    if shuffle {
-        builder = builder.shuffle(0);
+/// Like [`create_dataloader_v1`], but streams `corpus` rather than holding its windows in memory.
+/// With `shuffle_buffer`, items are shuffled within a buffer of that many, starting from `seed`.
+/// Batches are `Err` if the corpus can't be read or encoded part way through.
+#[allow(clippy::too_many_arguments)]
+fn create_streaming_dataloader_v1<B: Backend>(
+    corpus: Corpus,
+    config: &GPTConfig,
+    batch_size: usize,
+    max_length: usize,
+    stride: usize,
+    shuffle_buffer: Option<usize>,
+    seed: u64,
+    drop_last: bool,
+) -> Result<StreamingGPTDataLoader<B>> {
+    let tokenizer =
+        TiktokenTokenizer::new(Encoding::Gpt2, SpecialTokenPolicy::allow([END_OF_TEXT]))?;
+    // NB: the ids themselves aren't known until the corpus is read, so any of GPT-2's must fit.
+    validate_windows(max_length, tokenizer.vocab_size(), config)?;
+
+    let mut dataset = StreamingDataset::new(corpus, Box::new(tokenizer), max_length, stride)?;
+    if let Some(size) = shuffle_buffer {
+        dataset = dataset.shuffle_buffer(size);
     }
 
-    builder.build(GPTDatasetV1::<N>::new_from_text(
-        txt,
-        Box::new(UnsafeBPETokenizer::new("gpt2")),
+    Ok(Arc::new(StreamingDataLoader::new(
+        dataset,
+        Arc::new(GPTDatasetBatcher::default()),
+        batch_size,
+        drop_last,
+        shuffle_buffer.map(|_| seed),
+    )))
+}
+
+/// Like [`create_dataloader_v1`], but splits `corpus` per `split` first. Only the training loader
+/// follows `order`; the others keep the dataset's order and batch size, without dropping a short
+/// last batch, as in the book.
+#[allow(clippy::too_many_arguments)]
+fn create_dataloaders_v1<B: Backend>(
+    corpus: &Corpus,
+    config: &GPTConfig,
+    split: &SplitConfig,
+    order: DataOrder,
+    max_length: usize,
+    stride: usize,
+    num_workers: usize,
+    pad_id: Option<usize>,
+) -> Result<Splits<ResumableGPTDataLoader<B>>> {
+    let datasets = GPTDatasetV1::split(
+        corpus,
+        Box::new(TiktokenTokenizer::new(
+            Encoding::Gpt2,
+            SpecialTokenPolicy::allow([END_OF_TEXT]),
+        )?),
         max_length,
         stride,
-    ))
+        pad_id,
+        split,
+    )?;
+
+    datasets.train.validate(config)?;
+    datasets.validation.validate(config)?;
+    if let Some(test) = &datasets.test {
+        test.validate(config)?;
+    }
+
+    let evaluation = DataOrder::new(order.batch_size);
+    Ok(Splits {
+        train: dataloader(
+            datasets.train,
+            GPTDatasetBatcher::default(),
+            order,
+            num_workers,
+        )?,
+        validation: dataloader(
+            datasets.validation,
+            GPTDatasetBatcher::default(),
+            evaluation.clone(),
+            num_workers,
+        )?,
+        test: datasets
+            .test
+            .map(|test| dataloader(test, GPTDatasetBatcher::default(), evaluation, num_workers))
+            .transpose()?,
+    })
+}
+
+/// Like [`create_dataloader_v1`], but with an item per text, as for instruction data. Each text
+/// is followed by an `<|endoftext|>`, which also pads the inputs, while padded targets become
+/// [`IGNORE_INDEX`], as in the book's `custom_collate_fn`.
+fn create_padded_dataloader_v1<B: Backend>(
+    texts: Vec<String>,
+    config: &GPTConfig,
+    order: DataOrder,
+    max_length: Option<usize>,
+    num_workers: usize,
+) -> Result<ResumableGPTDataLoader<B>> {
+    let tokenizer =
+        TiktokenTokenizer::new(Encoding::Gpt2, SpecialTokenPolicy::allow([END_OF_TEXT]))?;
+    let end_of_text = tokenizer.encode(END_OF_TEXT.to_string())?;
+    let sequences = tokenizer
+        .encode_batch(texts)?
+        .into_iter()
+        .map(|mut ids| {
+            ids.extend_from_slice(&end_of_text);
+            ids
+        })
+        .collect();
+
+    let dataset = GPTDatasetV1::new_from_sequences(sequences, max_length)?;
+    dataset.validate(config)?;
+
+    dataloader(
+        dataset,
+        GPTDatasetBatcher::new(end_of_text[0], Some(IGNORE_INDEX)),
+        order,
+        num_workers,
+    )
+}
+
+fn dataloader<B: Backend>(
+    dataset: GPTDatasetV1,
+    batcher: GPTDatasetBatcher,
+    order: DataOrder,
+    num_workers: usize,
+) -> Result<ResumableGPTDataLoader<B>> {
+    info!(
+        batch_size = order.batch_size,
+        seed:? = order.seed,
+        drop_last = order.drop_last,
+        epoch = order.epoch,
+        batch = order.batch;
+        "creating data loader"
+    );
+
+    Ok(Arc::new(ResumableDataLoader::new(
+        dataset,
+        batcher,
+        order,
+        num_workers > 0,
+    )?))
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{
-        collections::HashSet,
-        fs::{self, File},
-        io,
-        marker::PhantomData,
-    };
+    use std::{collections::HashSet, fs};
 
     use burn::{
         Tensor,
         backend::{Wgpu, ndarray::NdArrayDevice, wgpu::WgpuDevice},
-        data::dataset::Dataset,
+        data::{
+            dataloader::{DataLoader, batcher::Batcher},
+            dataset::Dataset,
+        },
         nn::{Embedding, EmbeddingConfig},
+        tensor::TensorData,
     };
     use log::{debug, info};
 
-    use crate::listings::ch02::{
-        Corpus, GPTDatasetBatcher, GPTDatasetV1, SimpleTokenizerV1, SimpleTokenizerV2,
-        THE_VERDICT_URL, Tokenizer, construct_vocab_from_url, create_dataloader_v1, text_from_url,
-        tokenize,
+    use crate::{
+        error::Error,
+        listings::{
+            ch02::{
+                Corpus, END_OF_TEXT, GPTDatasetBatch, GPTDatasetBatcher, GPTDatasetV1,
+                IGNORE_INDEX, SimpleTokenizerV1, SimpleTokenizerV2, THE_VERDICT_URL, Tokenizer,
+                construct_vocab_from_url, create_dataloader_v1, create_dataloaders_v1,
+                create_padded_dataloader_v1, create_streaming_dataloader_v1,
+                order::DataOrder,
+                split_for_encoding,
+                splits::{SplitBy, SplitConfig},
+                text_from_url, tokenize,
+                tokenizers::{Encoding, SpecialTokenPolicy, TiktokenTokenizer},
+            },
+            ch04::GPTConfig,
+        },
     };
 
     use burn::data::dataloader::DataLoaderBuilder;
 
     use rstest::{fixture, rstest};
+    use tempfile::TempDir;
 
     #[test]
     fn test_simple_tokenizer_v2_special_tokens() {
-        let tokenizer = SimpleTokenizerV2::new(Corpus::Url(THE_VERDICT_URL.to_string()));
+        let tokenizer = SimpleTokenizerV2::new(Corpus::Url(THE_VERDICT_URL.to_string())).unwrap();
 
         assert_eq!(tokenizer.str_to_int["younger"], 1127);
         assert_eq!(tokenizer.str_to_int["your"], 1128);
@@ -389,11 +1228,11 @@ mod tests {
 
     #[test]
     fn test_simple_tokenizer_v2_roundtrip() {
-        let tokenizer = SimpleTokenizerV2::new(Corpus::Url(THE_VERDICT_URL.to_string()));
+        let tokenizer = SimpleTokenizerV2::new(Corpus::Url(THE_VERDICT_URL.to_string())).unwrap();
 
         let input = "Hello, do you like tea? <|endoftext|> In the sunlit terraces of the palace.";
 
-        let encoded = tokenizer.encode(input.to_string());
+        let encoded = tokenizer.encode(input.to_string()).unwrap();
 
         assert_eq!(
             encoded,
@@ -402,7 +1241,7 @@ mod tests {
             ],
         );
 
-        let decoded = tokenizer.decode(encoded);
+        let decoded = tokenizer.decode(encoded).unwrap();
 
         assert_eq!(
             decoded,
@@ -419,7 +1258,7 @@ mod tests {
         let input =
             "\"It's the last he painted, you know,\" Mrs. Gisburn said with pardonable pride.";
 
-        let ids = tokenizer.encode(input.to_string());
+        let ids = tokenizer.encode(input.to_string()).unwrap();
 
         assert_eq!(
             ids,
@@ -430,11 +1269,105 @@ mod tests {
         );
 
         assert_eq!(
-            tokenizer.decode(ids),
+            tokenizer.decode(ids).unwrap(),
             "\" It' s the last he painted, you know,\" Mrs. Gisburn said with pardonable pride."
         );
     }
 
+    #[test]
+    fn test_simple_tokenizer_errors() {
+        let vocab = Corpus::Raw("the cat sat.".to_string())
+            .to_vocab(None)
+            .unwrap();
+        let tokenizer = SimpleTokenizerV1::new(vocab);
+
+        assert!(matches!(
+            tokenizer.encode("the dog sat.".to_string()),
+            Err(Error::UnknownToken(token)) if token == "dog"
+        ));
+        assert!(matches!(
+            tokenizer.decode(vec![0, 42]),
+            Err(Error::UnknownId(42))
+        ));
+    }
+
+    #[test]
+    fn test_simple_tokenizer_offsets() {
+        let tokenizer = SimpleTokenizerV2::new(Corpus::Raw("the café sat.".to_string())).unwrap();
+
+        let text = "the café, sat";
+        let spans = tokenizer.encode_with_offsets(text).unwrap();
+
+        assert_eq!(
+            spans.iter().map(|span| span.id).collect::<Vec<_>>(),
+            tokenizer.encode(text.to_string()).unwrap()
+        );
+        assert_eq!(
+            spans
+                .iter()
+                .map(|span| &text[span.bytes.clone()])
+                .collect::<Vec<_>>(),
+            ["the", "café", ",", "sat"]
+        );
+        assert_eq!(spans[2].bytes, 9..10);
+        assert_eq!(spans[2].chars, 8..9);
+        assert_eq!(spans[2].id, tokenizer.str_to_int["<|unk|>"]);
+
+        assert_eq!(
+            tokenizer.decode_bytes(spans[1].id).unwrap(),
+            "café".as_bytes()
+        );
+        assert!(matches!(
+            tokenizer.decode_bytes(99),
+            Err(Error::UnknownId(99))
+        ));
+    }
+
+    #[test]
+    fn test_parallel_encoding_matches_serial() {
+        let text = "I HAD always thought Jack Gisburn rather a cheap genius--though a good fellow \
+                    enough--so it was no great surprise to me to hear that, in the height of his \
+                    glory,  he had dropped his painting,\n\nmarried a rich widow, and \
+                    established himself in a villa on the Riviera. <|endoftext|> (Though I \
+                    rather thought it would have been Rome or Florence.)";
+
+        let chunks = split_for_encoding(text, 16);
+        assert!(chunks.len() > 10);
+        assert_eq!(chunks.concat(), text);
+
+        // The target can fall inside a multi-byte character.
+        let accented = "é".repeat(10) + " x y";
+        assert_eq!(split_for_encoding(&accented, 3), ["éééééééééé", " x y"]);
+
+        let tokenizers: Vec<Box<dyn Tokenizer>> = vec![
+            Box::new(SimpleTokenizerV2::new(Corpus::Raw(text.to_string())).unwrap()),
+            Box::new(
+                TiktokenTokenizer::new(Encoding::Gpt2, SpecialTokenPolicy::allow([END_OF_TEXT]))
+                    .unwrap(),
+            ),
+            Box::new(
+                TiktokenTokenizer::new(Encoding::Cl100k, SpecialTokenPolicy::allow([END_OF_TEXT]))
+                    .unwrap(),
+            ),
+        ];
+        for tokenizer in tokenizers {
+            let expected = tokenizer.encode(text.to_string()).unwrap();
+            let chunked: Vec<usize> = chunks
+                .iter()
+                .flat_map(|chunk| tokenizer.encode(chunk.to_string()).unwrap())
+                .collect();
+            assert_eq!(chunked, expected);
+
+            assert_eq!(tokenizer.encode_parallel(text).unwrap(), expected);
+            assert_eq!(
+                tokenizer
+                    .encode_batch(vec![text.to_string(), String::new()])
+                    .unwrap(),
+                [expected, vec![]]
+            );
+        }
+    }
+
     #[test]
     fn test_tokenize() {
         assert_eq!(
@@ -444,21 +1377,7 @@ mod tests {
             ])
         );
 
-        let client = reqwest::blocking::Client::new();
-
-        let path = "/tmp/the-verdict.txt";
-
-        let mut res = client
-            .get(THE_VERDICT_URL)
-            .send()
-            .unwrap()
-            .error_for_status()
-            .unwrap();
-        let mut file = File::create(path).unwrap();
-
-        io::copy(&mut res, &mut file).unwrap();
-
-        let opened = fs::read_to_string(path).unwrap();
+        let opened = text_from_url(THE_VERDICT_URL.to_string()).unwrap();
 
         assert_eq!(
             tokenize(&opened)[0..30],
@@ -534,8 +1453,6 @@ mod tests {
         assert_eq!(enc_sample[1..context_size + 1], [4920, 2241, 287, 257]);
     }
 
-    type MaxLength<const N: usize> = PhantomData<GPTDatasetV1<N>>;
-
     #[fixture]
     #[once]
     fn init_logger() -> () {
@@ -545,11 +1462,10 @@ mod tests {
     }
 
     #[rstest]
-    fn test_gpt_v1_dataset<const N: usize>(
+    fn test_gpt_v1_dataset(
         #[expect(unused_variables)] init_logger: &(),
         #[values(4, 6)] batch_size: usize,
-        // NB(@jinnovation): See: https://github.com/la10736/rstest/issues/300
-        #[values(MaxLength::<4>::default(), MaxLength::<6>::default())] _max_length: MaxLength<N>,
+        #[values(4, 6)] max_length: usize,
         #[values(1, 2, 3)] stride: usize,
     ) {
         // ref: https://github.com/tracel-ai/burn/blob/439a26c0ff35c8557e0105786e7ce0d2b74c2c4b/examples/custom-image-dataset/examples/custom-image-dataset.rs
@@ -557,23 +1473,25 @@ mod tests {
 
         type Backend = NdArray;
 
-        let dataset = GPTDatasetV1::<N>::new_from_text(
+        let dataset = GPTDatasetV1::new_from_text(
             text_from_url(THE_VERDICT_URL.to_string()).unwrap(),
-            Box::new(SimpleTokenizerV2::new(Corpus::Url(
-                THE_VERDICT_URL.to_string(),
-            ))),
-            N,
+            Box::new(SimpleTokenizerV2::new(Corpus::Url(THE_VERDICT_URL.to_string())).unwrap()),
+            max_length,
             stride,
-        );
+            None,
+        )
+        .unwrap();
 
         let item = dataset.get(0).unwrap();
 
         let enc_text = SimpleTokenizerV2::new(Corpus::Url(THE_VERDICT_URL.to_string()))
-            .encode(text_from_url(THE_VERDICT_URL.to_string()).unwrap());
+            .unwrap()
+            .encode(text_from_url(THE_VERDICT_URL.to_string()).unwrap())
+            .unwrap();
 
-        assert_eq!(item.input_ids.to_vec(), enc_text[0..N]);
+        assert_eq!(item.input_ids, enc_text[0..max_length]);
 
-        let dataloader = DataLoaderBuilder::<Backend, _, _>::new(GPTDatasetBatcher {})
+        let dataloader = DataLoaderBuilder::<Backend, _, _>::new(GPTDatasetBatcher::default())
             .batch_size(batch_size)
             // .shuffle(0)
             .num_workers(0)
@@ -581,10 +1499,415 @@ mod tests {
 
         let batch = dataloader.iter().next().unwrap();
 
-        info!(batch:?, stride, _max_length:?; "retrieved batch");
+        info!(batch:?, stride, max_length; "retrieved batch");
+
+        assert_eq!(batch.input_ids.shape().dims, [batch_size, max_length]);
+        assert_eq!(batch.target_ids.shape().dims, [batch_size, max_length]);
+    }
+
+    #[test]
+    fn test_gpt_v1_dataset_from_corpus() {
+        let tokenizer = SimpleTokenizerV2::new(Corpus::Raw(
+            "the cat sat on the mat. the dog sat on the log.".to_string(),
+        ))
+        .unwrap();
+        let separator = tokenizer.str_to_int["<|endoftext|>"];
+
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("1.txt"), "the cat sat").unwrap();
+        fs::write(dir.path().join("2.txt"), "the dog sat").unwrap();
+
+        let dataset = GPTDatasetV1::new_from_corpus(
+            &Corpus::Dir(dir.path().to_path_buf()),
+            Box::new(tokenizer),
+            3,
+            1,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(dataset.len(), 4);
+        assert_eq!(dataset.max_length(), 3);
+        assert_eq!(dataset.get(1).unwrap().input_ids[2], separator);
+        assert_eq!(dataset.get(3).unwrap().target_ids, [7, 2, 6]);
+    }
+
+    #[test]
+    fn test_gpt_v1_dataset_validate() {
+        let dataset = GPTDatasetV1::new_from_token_ids((0..32).collect(), 8, 4, None).unwrap();
+        assert_eq!(dataset.len(), 6);
+        assert_eq!(
+            dataset.get(5).unwrap().target_ids,
+            (21..29).collect::<Vec<_>>()
+        );
+
+        dataset.validate(&GPTConfig::new()).unwrap();
+        assert!(matches!(
+            dataset.validate(&GPTConfig::new().with_context_length(4)),
+            Err(Error::ContextTooLong {
+                max_length: 8,
+                context_length: 4
+            })
+        ));
+        assert!(matches!(
+            dataset.validate(&GPTConfig::new().with_vocab_size(16)),
+            Err(Error::UnknownId(31))
+        ));
+
+        assert!(matches!(
+            GPTDatasetV1::new_from_token_ids((0..32).collect(), 0, 4, None),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_gpt_v1_dataset_padding() {
+        assert!(matches!(
+            GPTDatasetV1::new_from_token_ids((0..4).collect(), 4, 1, None),
+            Err(Error::CorpusTooSmall {
+                tokens: 4,
+                required: 5
+            })
+        ));
+        assert!(matches!(
+            GPTDatasetV1::new_from_token_ids(vec![7], 4, 1, Some(0)),
+            Err(Error::CorpusTooSmall {
+                tokens: 1,
+                required: 2
+            })
+        ));
+
+        let dataset = GPTDatasetV1::new_from_token_ids((1..4).collect(), 4, 1, Some(0)).unwrap();
+        assert_eq!(dataset.len(), 1);
+        let item = dataset.get(0).unwrap();
+        assert_eq!(item.input_ids, [1, 2, 0, 0]);
+        assert_eq!(item.target_ids, [2, 3, 0, 0]);
+        assert_eq!(item.attention_mask, [true, true, false, false]);
+
+        let dataset = GPTDatasetV1::new_from_token_ids((1..12).collect(), 4, 4, Some(50)).unwrap();
+        assert_eq!(dataset.len(), 3);
+        assert_eq!(dataset.get(1).unwrap().attention_mask, [true; 4]);
+        let item = dataset.get(2).unwrap();
+        assert_eq!(item.input_ids, [9, 10, 50, 50]);
+        assert_eq!(item.target_ids, [10, 11, 50, 50]);
+        assert!(matches!(
+            dataset.validate(&GPTConfig::new().with_vocab_size(50)),
+            Err(Error::UnknownId(50))
+        ));
+    }
+
+    #[test]
+    fn test_gpt_v1_dataset_packed() {
+        use burn::backend::NdArray;
+
+        let tokenizer = || {
+            Box::new(
+                SimpleTokenizerV2::new(Corpus::Raw("the cat sat on the mat. the dog".to_string()))
+                    .unwrap(),
+            )
+        };
+        let end_of_text = tokenizer().str_to_int[END_OF_TEXT];
+
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("1.txt"), "the cat sat").unwrap();
+        fs::write(dir.path().join("2.txt"), "the dog").unwrap();
+        fs::write(dir.path().join("3.txt"), "on the mat").unwrap();
+        let corpus = Corpus::Dir(dir.path().to_path_buf());
+
+        // 10 ids: "the cat sat <|endoftext|> the dog <|endoftext|> on the mat".
+        let dataset = GPTDatasetV1::new_packed(&corpus, tokenizer(), 4, 4, true).unwrap();
+        assert_eq!(dataset.len(), 2);
+        let item = dataset.get(1).unwrap();
+        assert_eq!(item.input_ids[2], end_of_text);
+        assert_eq!(item.document_ids, Some(vec![1, 1, 1, 2]));
+
+        let batch: GPTDatasetBatch<NdArray> = GPTDatasetBatcher::default()
+            .batch(vec![dataset.get(0).unwrap(), item], &Default::default());
+        batch.position_ids.unwrap().into_data().assert_eq(
+            &TensorData::from([[0i64, 1, 2, 3], [0, 1, 2, 0]]).convert::<i64>(),
+            false,
+        );
+        let document_mask = batch.document_mask.unwrap();
+        assert_eq!(document_mask.dims(), [2, 4, 4]);
+        document_mask
+            .slice([1..2, 3..4])
+            .into_data()
+            .assert_eq(&TensorData::from([[[false, false, false, true]]]), false);
+
+        let dataset = GPTDatasetV1::new_packed(&corpus, tokenizer(), 4, 4, false).unwrap();
+        let batch: GPTDatasetBatch<NdArray> =
+            GPTDatasetBatcher::default().batch(vec![dataset.get(0).unwrap()], &Default::default());
+        assert!(batch.document_mask.is_none() && batch.position_ids.is_none());
+    }
+
+    #[test]
+    fn test_gpt_dataset_batcher_padding() {
+        use burn::backend::NdArray;
+
+        let dataset =
+            GPTDatasetV1::new_from_sequences(vec![vec![1, 2, 3, 4, 5, 6], vec![7, 8, 9]], Some(4))
+                .unwrap();
+        assert_eq!(dataset.max_length(), 4);
+        assert_eq!(dataset.get(0).unwrap().target_ids, [2, 3, 4, 5]);
+        assert!(matches!(
+            GPTDatasetV1::new_from_sequences(vec![vec![1]], None),
+            Err(Error::CorpusTooSmall {
+                tokens: 1,
+                required: 2
+            })
+        ));
+
+        let items = vec![dataset.get(0).unwrap(), dataset.get(1).unwrap()];
+        let batch: GPTDatasetBatch<NdArray> =
+            GPTDatasetBatcher::new(0, Some(IGNORE_INDEX)).batch(items.clone(), &Default::default());
+        batch.input_ids.into_data().assert_eq(
+            &TensorData::from([[1i64, 2, 3, 4], [7, 8, 0, 0]]).convert::<i64>(),
+            false,
+        );
+        batch.target_ids.into_data().assert_eq(
+            &TensorData::from([[2i64, 3, 4, 5], [8, 9, -100, -100]]).convert::<i64>(),
+            false,
+        );
+        batch.attention_mask.into_data().assert_eq(
+            &TensorData::from([[true, true, true, true], [true, true, false, false]]),
+            false,
+        );
+
+        // An item's own padding is only replaced with an ignore index.
+        let item = GPTDatasetV1::new_from_token_ids((1..4).collect(), 4, 1, Some(0))
+            .unwrap()
+            .get(0)
+            .unwrap();
+        let batch: GPTDatasetBatch<NdArray> =
+            GPTDatasetBatcher::default().batch(vec![item.clone()], &Default::default());
+        batch
+            .target_ids
+            .into_data()
+            .assert_eq(&TensorData::from([[2i64, 3, 0, 0]]).convert::<i64>(), false);
+        let batch: GPTDatasetBatch<NdArray> =
+            GPTDatasetBatcher::new(0, Some(IGNORE_INDEX)).batch(vec![item], &Default::default());
+        batch.target_ids.into_data().assert_eq(
+            &TensorData::from([[2i64, 3, -100, -100]]).convert::<i64>(),
+            false,
+        );
+    }
+
+    #[test]
+    fn test_create_padded_dataloader() {
+        use burn::backend::NdArray;
+
+        let texts = vec!["Hello, world.".to_string(), "Hi".to_string()];
+        let dataloader = create_padded_dataloader_v1::<NdArray>(
+            texts,
+            &GPTConfig::new(),
+            DataOrder::new(2),
+            None,
+            0,
+        )
+        .unwrap();
+
+        // "Hello", ",", " world", "." and "<|endoftext|>", then "Hi" and "<|endoftext|>".
+        let batch = dataloader.iter().next().unwrap();
+        batch.input_ids.into_data().assert_eq(
+            &TensorData::from([[15496i64, 11, 995, 13], [17250, 50256, 50256, 50256]])
+                .convert::<i64>(),
+            false,
+        );
+        batch.target_ids.into_data().assert_eq(
+            &TensorData::from([[11i64, 995, 13, 50256], [50256, -100, -100, -100]])
+                .convert::<i64>(),
+            false,
+        );
+    }
+
+    #[rstest]
+    #[case(false, 3, 1)]
+    #[case(true, 2, 4)]
+    fn test_create_dataloader_drop_last(
+        #[case] drop_last: bool,
+        #[case] batches: usize,
+        #[case] last_batch_size: usize,
+    ) {
+        use burn::backend::NdArray;
+
+        // 11 ids, so 9 windows of 2 with a stride of 1.
+        let txt = "a b c d e f g h i j k".to_string();
+        let dataloader = create_dataloader_v1::<NdArray>(
+            txt,
+            &GPTConfig::new(),
+            DataOrder::new(4)
+                .with_seed(Some(0))
+                .with_drop_last(drop_last),
+            2,
+            1,
+            0,
+            None,
+        )
+        .unwrap();
+
+        let sizes: Vec<_> = dataloader
+            .iter()
+            .map(|batch| batch.input_ids.shape().dims[0])
+            .collect();
+        assert_eq!(sizes.len(), batches);
+        assert_eq!(sizes.last(), Some(&last_batch_size));
+
+        let batch = dataloader.iter().next().unwrap();
+        assert_eq!(batch.attention_mask.shape().dims, [4, 2]);
+    }
+
+    #[test]
+    fn test_gpt_v1_dataset_split_tokens() {
+        let text = "the cat sat on the mat. the dog sat on the log. the cat saw the dog.";
+        let tokenizer = || Box::new(SimpleTokenizerV2::new(Corpus::Raw(text.to_string())).unwrap());
+        let token_ids = tokenizer().encode(text.to_string()).unwrap();
+
+        let config = SplitConfig::new()
+            .with_train_ratio(0.5)
+            .with_validation_ratio(0.5);
+        let splits = GPTDatasetV1::split(
+            &Corpus::Raw(text.to_string()),
+            tokenizer(),
+            3,
+            1,
+            None,
+            &config,
+        )
+        .unwrap();
+        assert!(splits.test.is_none());
+
+        // Each split is windowed as if it were the whole corpus.
+        let train_end = token_ids.len().div_ceil(2);
+        let train =
+            GPTDatasetV1::new_from_token_ids(token_ids[..train_end].to_vec(), 3, 1, None).unwrap();
+        let validation =
+            GPTDatasetV1::new_from_token_ids(token_ids[train_end..].to_vec(), 3, 1, None).unwrap();
+        for (split, expected) in [(splits.train, train), (splits.validation, validation)] {
+            assert_eq!(split.len(), expected.len());
+            for index in 0..split.len() {
+                assert_eq!(
+                    split.get(index).unwrap().target_ids,
+                    expected.get(index).unwrap().target_ids
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_gpt_v1_dataset_split_documents() {
+        let tokenizer = || {
+            Box::new(
+                SimpleTokenizerV2::new(Corpus::Raw(
+                    "the cat sat on the mat. the dog sat on the log.".to_string(),
+                ))
+                .unwrap(),
+            )
+        };
+
+        let dir = TempDir::new().unwrap();
+        for (i, text) in ["the cat sat", "the dog sat", "on the mat", "on the log"]
+            .iter()
+            .enumerate()
+        {
+            fs::write(dir.path().join(format!("{i}.txt")), text).unwrap();
+        }
+        let corpus = Corpus::Dir(dir.path().to_path_buf());
+
+        let config = SplitConfig::new()
+            .with_train_ratio(0.5)
+            .with_validation_ratio(0.25)
+            .with_by(SplitBy::Documents);
+        let documents = corpus.split_documents(&config).unwrap();
+        assert_eq!(documents.train.len(), 2);
+        assert_eq!(documents.validation.len(), 1);
+        assert_eq!(documents.test.as_ref().map(Vec::len), Some(1));
+
+        let splits = GPTDatasetV1::split(&corpus, tokenizer(), 2, 1, None, &config).unwrap();
+        // Two documents and a separator, or one document alone.
+        assert_eq!(splits.train.len(), 5);
+        assert_eq!(splits.validation.len(), 1);
+        assert_eq!(splits.test.unwrap().len(), 1);
+
+        let again = GPTDatasetV1::split(&corpus, tokenizer(), 2, 1, None, &config).unwrap();
+        assert_eq!(
+            again.validation.get(0).unwrap().input_ids,
+            splits.validation.get(0).unwrap().input_ids
+        );
+    }
+
+    #[test]
+    fn test_create_streaming_dataloader() {
+        use burn::backend::NdArray;
+
+        let dataloader = create_streaming_dataloader_v1::<NdArray>(
+            Corpus::Raw("a b c d e f g h i j k".to_string()),
+            &GPTConfig::new(),
+            4,
+            2,
+            1,
+            Some(8),
+            0,
+            false,
+        )
+        .unwrap();
+        let sizes: Vec<_> = dataloader
+            .iter()
+            .map(|batch| batch.unwrap().input_ids.dims()[0])
+            .collect();
+        assert_eq!(sizes, [4, 4, 1]);
+
+        assert!(matches!(
+            create_streaming_dataloader_v1::<NdArray>(
+                Corpus::Raw(String::new()),
+                &GPTConfig::new().with_context_length(1),
+                4,
+                2,
+                1,
+                None,
+                0,
+                false,
+            ),
+            Err(Error::ContextTooLong { .. })
+        ));
+        assert!(matches!(
+            create_streaming_dataloader_v1::<NdArray>(
+                Corpus::Raw(String::new()),
+                &GPTConfig::new().with_vocab_size(1000),
+                4,
+                2,
+                1,
+                None,
+                0,
+                false,
+            ),
+            Err(Error::UnknownId(_))
+        ));
+    }
+
+    #[test]
+    fn test_create_dataloaders() {
+        use burn::backend::NdArray;
+
+        // 20 ids: 16 for training, and 2 each for validation and testing.
+        let text = "a b c d e f g h i j k l m n o p q r s t".to_string();
+        let splits = create_dataloaders_v1::<NdArray>(
+            &Corpus::Raw(text),
+            &GPTConfig::new(),
+            &SplitConfig::new()
+                .with_train_ratio(0.8)
+                .with_validation_ratio(0.1),
+            DataOrder::new(4).with_seed(Some(0)).with_drop_last(true),
+            1,
+            1,
+            0,
+            None,
+        )
+        .unwrap();
 
-        assert_eq!(batch.input_ids.shape().dims, [batch_size, N]);
-        assert_eq!(batch.target_ids.shape().dims, [batch_size, N]);
+        // 15 windows, less a short last batch.
+        assert_eq!(splits.train.iter().count(), 3);
+        assert_eq!(splits.validation.iter().count(), 1);
+        assert_eq!(splits.test.unwrap().iter().count(), 1);
     }
 
     #[rstest]
@@ -636,15 +1959,16 @@ mod tests {
 
         const MAX_LENGTH: usize = 4;
 
-        let dataloader = create_dataloader_v1::<Backend, MAX_LENGTH>(
+        let dataloader = create_dataloader_v1::<Backend>(
             text_from_url(THE_VERDICT_URL.to_string()).unwrap(),
-            8,
+            &GPTConfig::new(),
+            DataOrder::new(8),
             MAX_LENGTH,
             4,
-            false,
-            false,
             0,
-        );
+            None,
+        )
+        .unwrap();
 
         let batch = dataloader.iter().next().unwrap();
         debug!(input_ids:? = batch.input_ids, shape:? = batch.input_ids.shape() ; "retrieved batch");
@@ -0,0 +1,271 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, BufRead, BufReader},
+    iter,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{Error, Result},
+    listings::ch02::{
+        END_OF_TEXT,
+        splits::{SplitConfig, Splits},
+        text_from_url, tokenize,
+    },
+};
+
+/// Separator placed between documents when a multi-document corpus is flattened into one string.
+pub const DOCUMENT_SEPARATOR: &str = " <|endoftext|> ";
+
+pub type Document = Result<String>;
+
+/// A body of text, made up of one or more documents.
+pub enum Corpus {
+    Raw(String),
+    /// A corpus identified by its upstream URL, read via the configured
+    /// [`CorpusSource`](crate::listings::ch02::sources::CorpusSource).
+    Url(String),
+    File(PathBuf),
+    /// Every file under a directory, recursively, in path order.
+    Dir(PathBuf),
+    /// Every file matching a glob pattern, in path order.
+    Glob(String),
+    Stdin,
+    /// One document per line of a JSON Lines file, taken from the string at `field`.
+    Jsonl {
+        path: PathBuf,
+        field: String,
+    },
+}
+
+impl Corpus {
+    /// Reads a command-line argument as a corpus: `-` for stdin, an `http(s)://` URL, a glob
+    /// pattern, a directory, or otherwise a file.
+    pub fn from_arg(arg: &str) -> Self {
+        if arg == "-" {
+            Corpus::Stdin
+        } else if arg.starts_with("http://") || arg.starts_with("https://") {
+            Corpus::Url(arg.to_string())
+        } else if arg.contains(['*', '?', '[']) {
+            Corpus::Glob(arg.to_string())
+        } else if Path::new(arg).is_dir() {
+            Corpus::Dir(arg.into())
+        } else {
+            Corpus::File(arg.into())
+        }
+    }
+
+    /// Lazily yields the corpus one document at a time.
+    pub fn documents(&self) -> Result<Box<dyn Iterator<Item = Document> + '_>> {
+        Ok(match self {
+            Corpus::Raw(text) => Box::new(iter::once(Ok(text.clone()))),
+            Corpus::Url(url) => Box::new(iter::once_with(|| text_from_url(url.clone()))),
+            Corpus::File(_) | Corpus::Dir(_) | Corpus::Glob(_) => {
+                Box::new(self.files()?.map(|path| read_file(&path?)))
+            }
+            Corpus::Stdin => Box::new(iter::once_with(|| Ok(io::read_to_string(io::stdin())?))),
+            Corpus::Jsonl { path, field } => Box::new(jsonl(path, field)?),
+        })
+    }
+
+    /// The files that are each one whole document, in path order: those of a [`Corpus::File`],
+    /// [`Corpus::Dir`] or [`Corpus::Glob`], and none for any other corpus.
+    pub(super) fn files(&self) -> Result<Box<dyn Iterator<Item = Result<PathBuf>>>> {
+        let pattern = match self {
+            Corpus::File(path) => return Ok(Box::new(iter::once(Ok(path.clone())))),
+            Corpus::Dir(dir) => {
+                let dir = glob::Pattern::escape(&dir.to_string_lossy());
+                format!("{dir}/**/*")
+            }
+            Corpus::Glob(pattern) => pattern.clone(),
+            _ => return Ok(Box::new(iter::empty())),
+        };
+
+        Ok(Box::new(glob::glob(&pattern)?.filter_map(
+            |entry| match entry {
+                Ok(path) if path.is_file() => Some(Ok(path)),
+                Ok(_) => None,
+                Err(e) => Some(Err(e.into())),
+            },
+        )))
+    }
+
+    /// The whole corpus as one string, with documents joined by [`DOCUMENT_SEPARATOR`].
+    pub fn text(&self) -> Result<String> {
+        Ok(self
+            .documents()?
+            .collect::<Result<Vec<_>>>()?
+            .join(DOCUMENT_SEPARATOR))
+    }
+
+    /// Deals the corpus's documents out into splits, shuffled by `config`'s seed. Unlike
+    /// [`Corpus::documents`], this reads the whole corpus in.
+    pub fn split_documents(&self, config: &SplitConfig) -> Result<Splits<Vec<String>>> {
+        config.validate()?;
+        let documents = self.documents()?.collect::<Result<Vec<_>>>()?;
+        Ok(config.shuffle_and_cut(documents))
+    }
+
+    /// Builds a vocabulary over every document without holding the whole corpus in memory.
+    ///
+    /// `<|endoftext|>` is only included if it's passed in `additional_tokens`, even when the corpus
+    /// has several documents.
+    pub fn to_vocab(
+        &self,
+        additional_tokens: Option<Vec<String>>,
+    ) -> Result<HashMap<String, usize>> {
+        let mut words = HashSet::new();
+        for document in self.documents()? {
+            words.extend(
+                tokenize(&document?)
+                    .into_iter()
+                    .filter(|token| *token != END_OF_TEXT)
+                    .map(str::to_string),
+            );
+        }
+
+        let mut all_words: Vec<_> = words.into_iter().collect();
+        all_words.sort();
+        if let Some(tokens) = additional_tokens {
+            all_words.extend(tokens);
+        }
+
+        Ok(all_words
+            .into_iter()
+            .enumerate()
+            .map(|(id, word)| (word, id))
+            .collect())
+    }
+}
+
+pub(super) fn read_file(path: &PathBuf) -> Document {
+    fs::read_to_string(path).map_err(|source| Error::File {
+        path: path.clone(),
+        source,
+    })
+}
+
+fn jsonl<'a>(path: &PathBuf, field: &'a str) -> Result<impl Iterator<Item = Document> + 'a> {
+    let file = File::open(path).map_err(|source| Error::File {
+        path: path.clone(),
+        source,
+    })?;
+    let lines = BufReader::new(file).lines().enumerate();
+    let path = path.display().to_string();
+
+    Ok(lines.filter_map(move |(i, line)| {
+        let line = match line {
+            Ok(line) if line.trim().is_empty() => return None,
+            Ok(line) => line,
+            Err(e) => return Some(Err(e.into())),
+        };
+
+        Some(parse_line(&line, field, || format!("{path}:{}", i + 1)))
+    }))
+}
+
+/// The string at `field` of a JSON Lines `line`, with errors pointing at `location`.
+pub(super) fn parse_line(line: &str, field: &str, location: impl Fn() -> String) -> Document {
+    let invalid = |reason: String| Error::InvalidDocument {
+        location: location(),
+        reason,
+    };
+    serde_json::from_str::<serde_json::Value>(line)
+        .map_err(|e| invalid(e.to_string()))
+        .and_then(|value| match value.get(field) {
+            Some(serde_json::Value::String(text)) => Ok(text.clone()),
+            _ => Err(invalid(format!("no string field {field:?}"))),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use crate::{error::Error, listings::ch02::corpus::Corpus};
+
+    fn documents(corpus: Corpus) -> Vec<String> {
+        corpus
+            .documents()
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_file_dir_and_glob() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("nested")).unwrap();
+        fs::write(dir.path().join("b.txt"), "Second document.").unwrap();
+        fs::write(dir.path().join("a.txt"), "First document.").unwrap();
+        fs::write(dir.path().join("nested/c.md"), "Third document.").unwrap();
+
+        assert_eq!(
+            documents(Corpus::File(dir.path().join("a.txt"))),
+            ["First document."]
+        );
+
+        assert_eq!(
+            documents(Corpus::Dir(dir.path().to_path_buf())),
+            ["First document.", "Second document.", "Third document."]
+        );
+
+        let corpus = Corpus::Glob(dir.path().join("*.txt").to_string_lossy().to_string());
+        assert_eq!(
+            corpus.text().unwrap(),
+            "First document. <|endoftext|> Second document."
+        );
+    }
+
+    #[test]
+    fn test_jsonl() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("docs.jsonl");
+        fs::write(
+            &path,
+            "{\"id\": 1, \"text\": \"Hello, world.\"}\n\n{\"id\": 2, \"text\": \"Is this-- a test?\"}\n",
+        )
+        .unwrap();
+
+        let corpus = Corpus::Jsonl {
+            path: path.clone(),
+            field: "text".into(),
+        };
+        assert_eq!(documents(corpus), ["Hello, world.", "Is this-- a test?"]);
+
+        let corpus = Corpus::Jsonl {
+            path,
+            field: "body".into(),
+        };
+        assert!(matches!(
+            corpus.documents().unwrap().next().unwrap(),
+            Err(Error::InvalidDocument { .. })
+        ));
+    }
+
+    #[test]
+    fn test_to_vocab_across_documents() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("a.txt"), "the cat sat").unwrap();
+        fs::write(dir.path().join("b.txt"), "the dog sat.").unwrap();
+
+        let vocab = Corpus::Dir(dir.path().to_path_buf())
+            .to_vocab(vec!["<|endoftext|>".to_string(), "<|unk|>".to_string()].into())
+            .unwrap();
+
+        assert_eq!(vocab.len(), 7);
+        assert_eq!(vocab["."], 0);
+        assert_eq!(vocab["sat"], 3);
+        assert_eq!(vocab["the"], 4);
+        assert_eq!(vocab["<|endoftext|>"], 5);
+        assert_eq!(vocab["<|unk|>"], 6);
+
+        assert!(matches!(
+            Corpus::File(dir.path().join("missing.txt")).to_vocab(None),
+            Err(Error::File { .. })
+        ));
+    }
+}
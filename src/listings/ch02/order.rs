@@ -0,0 +1,430 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use burn::{
+    config::Config,
+    data::{
+        dataloader::{DataLoader, DataLoaderIterator, Progress, batcher::Batcher},
+        dataset::{Dataset, transform::PartialDataset},
+    },
+    prelude::Backend,
+};
+use rand::{SeedableRng, rngs::StdRng, seq::SliceRandom};
+use rayon::prelude::*;
+
+use crate::error::{Error, Result};
+
+/// The order a [`ResumableDataLoader`] yields batches in, and how far through it a run has got.
+/// Saving it alongside a run's other metadata, and passing it back to a new loader, picks up with
+/// exactly the batch the run would have seen next.
+#[derive(Config, Debug, PartialEq)]
+pub struct DataOrder {
+    pub batch_size: usize,
+    /// Shuffles every pass with its own permutation, derived from the seed and the pass's number.
+    /// Without one, every pass keeps the dataset's order.
+    pub seed: Option<u64>,
+    /// Drop each pass's last batch if it's short.
+    #[config(default = false)]
+    pub drop_last: bool,
+    /// Passes already finished.
+    #[config(default = 0)]
+    pub epoch: usize,
+    /// Batches already yielded from the current pass.
+    #[config(default = 0)]
+    pub batch: usize,
+}
+
+impl DataOrder {
+    pub fn validate(&self) -> Result<()> {
+        if self.batch_size == 0 {
+            return Err(Error::Config("batch_size must be positive".to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// The order of the current pass over a dataset of `len` items.
+    fn indices(&self, len: usize) -> Vec<usize> {
+        let mut indices: Vec<usize> = (0..len).collect();
+        if let Some(seed) = self.seed {
+            // NB: each pass gets its own rng, so that a pass's order doesn't depend on the passes
+            // before it having been iterated in this process.
+            let mut key = [0; 32];
+            key[..8].copy_from_slice(&seed.to_le_bytes());
+            key[8..16].copy_from_slice(&(self.epoch as u64).to_le_bytes());
+            indices.shuffle(&mut StdRng::from_seed(key));
+        }
+        indices
+    }
+
+    fn batches(&self, len: usize) -> usize {
+        if self.drop_last {
+            len / self.batch_size
+        } else {
+            len.div_ceil(self.batch_size)
+        }
+    }
+}
+
+/// A data loader that follows a [`DataOrder`], and keeps it up to date as batches are yielded.
+/// Each call to [`DataLoader::iter`] carries on from where the last one stopped, finishing the
+/// current pass.
+///
+/// Slices share their parent's order, so that splitting the loader across devices still keeps
+/// [`ResumableDataLoader::order`] up to date. Its `batch` then counts each device's batches, up to
+/// the slowest device's, and the epoch only moves on once every slice has finished its pass, so a
+/// run should be resumed on the same number of devices.
+pub struct ResumableDataLoader<B: Backend, I, O> {
+    dataset: Arc<dyn Dataset<I>>,
+    batcher: Arc<dyn Batcher<B, I, O>>,
+    tracker: Arc<Mutex<Tracker>>,
+    /// This loader's place in the tracker, if it's a slice.
+    slice: Option<usize>,
+    /// Fetch each batch's items across threads.
+    parallel: bool,
+    device: B::Device,
+}
+
+impl<B: Backend, I, O> ResumableDataLoader<B, I, O> {
+    pub fn new(
+        dataset: impl Dataset<I> + 'static,
+        batcher: impl Batcher<B, I, O> + 'static,
+        order: DataOrder,
+        parallel: bool,
+    ) -> Result<Self> {
+        order.validate()?;
+
+        Ok(Self {
+            dataset: Arc::new(dataset),
+            batcher: Arc::new(batcher),
+            tracker: Arc::new(Mutex::new(Tracker {
+                order,
+                slices: Vec::new(),
+            })),
+            slice: None,
+            parallel,
+            device: B::Device::default(),
+        })
+    }
+
+    /// A snapshot of where the loader is, to save with a run.
+    pub fn order(&self) -> DataOrder {
+        self.tracker().order.clone()
+    }
+
+    fn tracker(&self) -> MutexGuard<'_, Tracker> {
+        self.tracker.lock().expect("the order lock was poisoned")
+    }
+}
+
+/// A loader's [`DataOrder`], shared with its slices, and how far each slice has got through the
+/// current pass.
+#[derive(Debug)]
+struct Tracker {
+    order: DataOrder,
+    /// The batches each slice has yielded this pass, or `None` once it's finished the pass.
+    slices: Vec<Option<usize>>,
+}
+
+impl Tracker {
+    /// Records that `slice`, or the loader itself, has yielded `batch` of the pass's `batches`.
+    fn record(&mut self, slice: Option<usize>, batch: usize, batches: usize) {
+        let finished = batch >= batches;
+        let Some(slice) = slice else {
+            if finished {
+                self.next_pass();
+            } else {
+                self.order.batch = batch;
+            }
+            return;
+        };
+
+        self.slices[slice] = (!finished).then_some(batch);
+        match self.slices.iter().flatten().min() {
+            Some(slowest) => self.order.batch = *slowest,
+            None => self.next_pass(),
+        }
+    }
+
+    fn next_pass(&mut self) {
+        self.order.epoch += 1;
+        self.order.batch = 0;
+        self.slices.fill(Some(0));
+    }
+
+    /// The batch `slice`, or the loader itself, carries on from, which is `batches` if it's
+    /// finished the pass and is waiting for the other slices.
+    fn start(&self, slice: Option<usize>, batches: usize) -> usize {
+        match slice {
+            Some(slice) => self.slices[slice].unwrap_or(batches),
+            None => self.order.batch,
+        }
+    }
+}
+
+impl<B, I, O> DataLoader<B, O> for ResumableDataLoader<B, I, O>
+where
+    B: Backend,
+    I: Clone + Send + Sync + 'static,
+    O: 'static,
+{
+    fn iter<'a>(&'a self) -> Box<dyn DataLoaderIterator<O> + 'a> {
+        let len = self.dataset.len();
+        let mut tracker = self.tracker();
+        let batches = tracker.order.batches(len);
+
+        // NB: an order saved with another batch size or `drop_last` can be past the end of the
+        // pass, which finishes it.
+        if tracker.start(self.slice, batches) >= batches {
+            tracker.record(self.slice, batches, batches);
+        }
+
+        Box::new(ResumableDataLoaderIterator {
+            loader: self,
+            indices: tracker.order.indices(len),
+            batches,
+            batch: tracker.start(self.slice, batches),
+        })
+    }
+
+    fn num_items(&self) -> usize {
+        self.dataset.len()
+    }
+
+    fn to_device(&self, device: &B::Device) -> Arc<dyn DataLoader<B, O>> {
+        Arc::new(Self {
+            dataset: self.dataset.clone(),
+            batcher: self.batcher.clone(),
+            tracker: self.tracker.clone(),
+            slice: self.slice,
+            parallel: self.parallel,
+            device: device.clone(),
+        })
+    }
+
+    fn slice(&self, start: usize, end: usize) -> Arc<dyn DataLoader<B, O>> {
+        let mut tracker = self.tracker();
+        let batch = tracker.order.batch;
+        tracker.slices.push(Some(batch));
+
+        Arc::new(Self {
+            dataset: Arc::new(PartialDataset::new(self.dataset.clone(), start, end)),
+            batcher: self.batcher.clone(),
+            tracker: self.tracker.clone(),
+            slice: Some(tracker.slices.len() - 1),
+            parallel: self.parallel,
+            device: self.device.clone(),
+        })
+    }
+}
+
+struct ResumableDataLoaderIterator<'a, B: Backend, I, O> {
+    loader: &'a ResumableDataLoader<B, I, O>,
+    indices: Vec<usize>,
+    batches: usize,
+    batch: usize,
+}
+
+impl<B: Backend, I: Send + Sync, O> Iterator for ResumableDataLoaderIterator<'_, B, I, O> {
+    type Item = O;
+
+    fn next(&mut self) -> Option<O> {
+        if self.batch >= self.batches {
+            return None;
+        }
+
+        let mut tracker = self.loader.tracker();
+        let batch_size = tracker.order.batch_size;
+        let start = self.batch * batch_size;
+        let end = (start + batch_size).min(self.indices.len());
+        let indices = &self.indices[start..end];
+
+        let dataset = &self.loader.dataset;
+        let items: Vec<I> = if self.loader.parallel {
+            indices.par_iter().filter_map(|i| dataset.get(*i)).collect()
+        } else {
+            indices.iter().filter_map(|i| dataset.get(*i)).collect()
+        };
+
+        self.batch += 1;
+        tracker.record(self.loader.slice, self.batch, self.batches);
+        drop(tracker);
+
+        Some(self.loader.batcher.batch(items, &self.loader.device))
+    }
+}
+
+impl<B: Backend, I: Send + Sync, O> DataLoaderIterator<O>
+    for ResumableDataLoaderIterator<'_, B, I, O>
+{
+    fn progress(&self) -> Progress {
+        let total = self.indices.len();
+        Progress::new(
+            (self.batch * self.loader.order().batch_size).min(total),
+            total,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use burn::{
+        backend::{NdArray, ndarray::NdArrayDevice},
+        config::Config,
+        data::{
+            dataloader::{DataLoader, batcher::Batcher, split::split_dataloader},
+            dataset::InMemDataset,
+        },
+    };
+    use tempfile::TempDir;
+
+    use crate::{
+        error::Error,
+        listings::ch02::{
+            GPTDatasetBatcher, GPTDatasetV1,
+            order::{DataOrder, ResumableDataLoader},
+        },
+    };
+
+    /// Batches the items as they are.
+    struct Collect;
+
+    impl Batcher<NdArray, usize, Vec<usize>> for Collect {
+        fn batch(&self, items: Vec<usize>, _device: &NdArrayDevice) -> Vec<usize> {
+            items
+        }
+    }
+
+    fn loader(order: DataOrder) -> ResumableDataLoader<NdArray, usize, Vec<usize>> {
+        ResumableDataLoader::new(InMemDataset::new((0..10).collect()), Collect, order, false)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_data_order() {
+        let unshuffled = loader(DataOrder::new(4));
+        assert_eq!(
+            unshuffled.iter().collect::<Vec<_>>(),
+            [vec![0, 1, 2, 3], vec![4, 5, 6, 7], vec![8, 9]]
+        );
+
+        let shuffled = loader(DataOrder::new(4).with_seed(Some(42)).with_drop_last(true));
+        let first: Vec<_> = shuffled.iter().collect();
+        let second: Vec<_> = shuffled.iter().collect();
+        assert_eq!(first.len(), 2);
+        assert_ne!(first, second, "every pass should be shuffled differently");
+        assert_eq!(shuffled.order().epoch, 2);
+
+        let again = loader(DataOrder::new(4).with_seed(Some(42)).with_drop_last(true));
+        assert_eq!(again.iter().collect::<Vec<_>>(), first);
+
+        assert!(matches!(
+            ResumableDataLoader::<NdArray, _, _>::new(
+                InMemDataset::new(vec![0]),
+                Collect,
+                DataOrder::new(0),
+                false
+            ),
+            Err(Error::Config(_))
+        ));
+        assert_ne!(
+            loader(DataOrder::new(4).with_seed(Some(7)).with_drop_last(true))
+                .iter()
+                .collect::<Vec<_>>(),
+            first
+        );
+    }
+
+    #[test]
+    fn test_resume() {
+        let order = DataOrder::new(3).with_seed(Some(123));
+        let uninterrupted = loader(order.clone());
+        let expected: Vec<_> = (0..3).flat_map(|_| uninterrupted.iter()).collect();
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("order.json");
+        let interrupted = loader(order);
+        let mut batches: Vec<_> = interrupted.iter().collect();
+        batches.extend(interrupted.iter().take(2));
+        interrupted.order().save(&path).unwrap();
+
+        let resumed = loader(DataOrder::load(&path).unwrap());
+        assert_eq!(resumed.order().epoch, 1);
+        assert_eq!(resumed.order().batch, 2);
+        batches.extend(resumed.iter());
+        batches.extend(resumed.iter());
+        assert_eq!(batches, expected);
+    }
+
+    #[test]
+    fn test_gpt_dataset_order() {
+        let dataset = GPTDatasetV1::new_from_token_ids((0..32).collect(), 4, 4, None).unwrap();
+        let loader = ResumableDataLoader::<NdArray, _, _>::new(
+            dataset,
+            GPTDatasetBatcher::default(),
+            DataOrder::new(2).with_seed(Some(0)),
+            true,
+        )
+        .unwrap();
+
+        let batch = loader.iter().next().unwrap();
+        assert_eq!(batch.input_ids.dims(), [2, 4]);
+        assert_eq!(loader.order().batch, 1);
+        // The slice carries on from the second of its two batches.
+        assert_eq!(loader.slice(0, 3).iter().count(), 1);
+    }
+
+    #[test]
+    fn test_split_order() {
+        let parent = Arc::new(loader(DataOrder::new(2).with_batch(1)));
+        let devices = [NdArrayDevice::Cpu, NdArrayDevice::Cpu];
+        let slices = split_dataloader(parent.clone(), &devices);
+
+        // Each slice picks up from the parent's batch, and the devices step through them together.
+        let mut iters: Vec<_> = slices.iter().map(|slice| slice.iter()).collect();
+        let step: Vec<_> = iters.iter_mut().map(|iter| iter.next().unwrap()).collect();
+        assert_eq!(step, [vec![2, 3], vec![7, 8]]);
+        assert_eq!((parent.order().epoch, parent.order().batch), (0, 2));
+
+        for iter in &mut iters {
+            assert_eq!(iter.next().unwrap().len(), 1);
+        }
+        assert_eq!((parent.order().epoch, parent.order().batch), (1, 0));
+    }
+
+    #[test]
+    fn test_uneven_split_order() {
+        // Slices of 2, 2, 2 and 4 items, so the last has two batches and the others one.
+        let devices = [NdArrayDevice::Cpu; 4];
+        let parent = Arc::new(loader(DataOrder::new(2)));
+        let slices = split_dataloader(parent.clone(), &devices);
+
+        let mut iters: Vec<_> = slices.iter().map(|slice| slice.iter()).collect();
+        for iter in &mut iters {
+            iter.next().unwrap();
+        }
+        // The pass isn't over until the last slice's second batch.
+        let saved = parent.order();
+        assert_eq!((saved.epoch, saved.batch), (0, 1));
+        assert_eq!(iters[3].next().unwrap(), [8, 9]);
+        assert_eq!((parent.order().epoch, parent.order().batch), (1, 0));
+
+        // Resuming from the saved order only has the last slice's second batch left.
+        let resumed = Arc::new(loader(saved));
+        let batches: Vec<Vec<_>> = split_dataloader(resumed.clone(), &devices)
+            .iter()
+            .map(|slice| slice.iter().collect())
+            .collect();
+        assert_eq!(batches, [vec![], vec![], vec![], vec![vec![8, 9]]]);
+        assert_eq!((resumed.order().epoch, resumed.order().batch), (1, 0));
+    }
+
+    #[test]
+    fn test_order_past_the_end() {
+        // Saved with a smaller batch size, so past the three batches of 4 there are now.
+        let loader = loader(DataOrder::new(4).with_batch(5));
+        assert_eq!(loader.iter().count(), 3);
+        assert_eq!((loader.order().epoch, loader.order().batch), (2, 0));
+    }
+}
@@ -0,0 +1,481 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use burn::data::dataset::Dataset;
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    listings::{
+        ch02::{GPTDatasetItem, Tokenizer, corpus::Corpus, encode_corpus, validate_windows},
+        ch04::GPTConfig,
+    },
+};
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// How many ids [`pretokenize`] writes to each shard: 128 MiB at [`TokenWidth::U16`].
+pub const SHARD_TOKENS: usize = 1 << 26;
+
+/// How many bytes each id takes up in a shard. Ids are stored little-endian.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenWidth {
+    U16,
+    U32,
+}
+
+impl TokenWidth {
+    /// The narrowest width that holds every id in a vocabulary of `vocab_size`.
+    pub fn for_vocab_size(vocab_size: usize) -> Self {
+        if vocab_size <= 1 << 16 {
+            Self::U16
+        } else {
+            Self::U32
+        }
+    }
+
+    fn bytes(self) -> usize {
+        match self {
+            Self::U16 => 2,
+            Self::U32 => 4,
+        }
+    }
+
+    fn encode(self, id: usize) -> Option<Vec<u8>> {
+        match self {
+            Self::U16 => u16::try_from(id).ok().map(|id| id.to_le_bytes().to_vec()),
+            Self::U32 => u32::try_from(id).ok().map(|id| id.to_le_bytes().to_vec()),
+        }
+    }
+
+    fn decode(self, bytes: &[u8]) -> usize {
+        match self {
+            Self::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as usize,
+            Self::U32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize,
+        }
+    }
+}
+
+/// Describes a directory of shards, which together hold one stream of token ids.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardManifest {
+    pub width: TokenWidth,
+    /// One past the largest id across every shard.
+    pub min_vocab_size: usize,
+    /// In stream order.
+    pub shards: Vec<Shard>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Shard {
+    /// Relative to the manifest's directory.
+    pub file: String,
+    pub tokens: usize,
+}
+
+impl ShardManifest {
+    pub fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(MANIFEST_FILE);
+        let json = fs::read_to_string(&path).map_err(|source| Error::File { path, source })?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    /// The number of ids across every shard.
+    pub fn tokens(&self) -> usize {
+        self.shards.iter().map(|shard| shard.tokens).sum()
+    }
+}
+
+/// Writes a stream of token ids to fixed-size shards under a directory, followed by their
+/// [`ShardManifest`] once [`ShardWriter::finish`]ed.
+pub struct ShardWriter {
+    dir: PathBuf,
+    shard_tokens: usize,
+    manifest: ShardManifest,
+    current: Option<(PathBuf, BufWriter<File>)>,
+}
+
+impl ShardWriter {
+    pub fn new(dir: impl Into<PathBuf>, width: TokenWidth, shard_tokens: usize) -> Result<Self> {
+        if shard_tokens == 0 {
+            return Err(Error::Config("shard_tokens must be positive".to_string()));
+        }
+
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(|source| Error::File {
+            path: dir.clone(),
+            source,
+        })?;
+
+        Ok(Self {
+            dir,
+            shard_tokens,
+            manifest: ShardManifest {
+                width,
+                min_vocab_size: 0,
+                shards: Vec::new(),
+            },
+            current: None,
+        })
+    }
+
+    /// Fails with [`Error::InvalidShard`] if an id doesn't fit in the writer's [`TokenWidth`].
+    pub fn write(&mut self, ids: &[usize]) -> Result<()> {
+        for &id in ids {
+            let full = self
+                .manifest
+                .shards
+                .last()
+                .is_none_or(|shard| shard.tokens == self.shard_tokens);
+            if full {
+                self.next_shard()?;
+            }
+            let Some((path, writer)) = &mut self.current else {
+                unreachable!("a shard was just opened");
+            };
+
+            let width = self.manifest.width;
+            let bytes = width.encode(id).ok_or_else(|| Error::InvalidShard {
+                path: path.clone(),
+                reason: format!("id {id} doesn't fit in {width:?}"),
+            })?;
+            writer.write_all(&bytes).map_err(|source| Error::File {
+                path: path.clone(),
+                source,
+            })?;
+
+            if let Some(shard) = self.manifest.shards.last_mut() {
+                shard.tokens += 1;
+            }
+            self.manifest.min_vocab_size = self.manifest.min_vocab_size.max(id + 1);
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<ShardManifest> {
+        self.flush()?;
+
+        let path = self.dir.join(MANIFEST_FILE);
+        let json = serde_json::to_string_pretty(&self.manifest)?;
+        fs::write(&path, json).map_err(|source| Error::File { path, source })?;
+
+        Ok(self.manifest)
+    }
+
+    fn next_shard(&mut self) -> Result<()> {
+        self.flush()?;
+
+        let file = format!("{:05}.bin", self.manifest.shards.len());
+        let path = self.dir.join(&file);
+        let writer = File::create(&path).map_err(|source| Error::File {
+            path: path.clone(),
+            source,
+        })?;
+
+        self.manifest.shards.push(Shard { file, tokens: 0 });
+        self.current = Some((path, BufWriter::new(writer)));
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some((path, mut writer)) = self.current.take() {
+            writer
+                .flush()
+                .map_err(|source| Error::File { path, source })?;
+        }
+        Ok(())
+    }
+}
+
+/// Encodes `corpus` into shards under `dir`, with documents joined the same way as
+/// [`GPTDatasetV1::new_from_corpus`](super::GPTDatasetV1) joins them.
+pub fn pretokenize(
+    corpus: &Corpus,
+    tokenizer: &dyn Tokenizer,
+    dir: impl Into<PathBuf>,
+    width: TokenWidth,
+) -> Result<ShardManifest> {
+    let mut writer = ShardWriter::new(dir, width, SHARD_TOKENS)?;
+    encode_corpus(corpus, tokenizer, |ids| writer.write(ids))?;
+    writer.finish()
+}
+
+/// The same windows [`GPTDatasetV1`](super::GPTDatasetV1) makes without padding, read on demand
+/// from memory-mapped shards rather than held in memory.
+pub struct ShardedDataset {
+    width: TokenWidth,
+    shards: Vec<Mmap>,
+    /// The position in the stream of each shard's first id.
+    offsets: Vec<usize>,
+    max_length: usize,
+    stride: usize,
+    len: usize,
+    min_vocab_size: usize,
+}
+
+impl ShardedDataset {
+    /// Fails with [`Error::InvalidShard`] if a shard's size doesn't match the manifest, or
+    /// [`Error::CorpusTooSmall`] if the shards hold no full window.
+    pub fn open(dir: impl AsRef<Path>, max_length: usize, stride: usize) -> Result<Self> {
+        if max_length == 0 || stride == 0 {
+            return Err(Error::Config(format!(
+                "max_length ({max_length}) and stride ({stride}) must both be positive"
+            )));
+        }
+
+        let dir = dir.as_ref();
+        let manifest = ShardManifest::load(dir)?;
+
+        let mut shards = Vec::with_capacity(manifest.shards.len());
+        let mut offsets = Vec::with_capacity(manifest.shards.len());
+        let mut tokens = 0;
+        for shard in &manifest.shards {
+            let path = dir.join(&shard.file);
+            let file = File::open(&path).map_err(|source| Error::File {
+                path: path.clone(),
+                source,
+            })?;
+            // NB: the map is only sound as long as nothing truncates the shard while it's open,
+            // which holds for shards that are written once by `ShardWriter` and then only read.
+            let mmap = unsafe { Mmap::map(&file) }.map_err(|source| Error::File {
+                path: path.clone(),
+                source,
+            })?;
+
+            let expected = shard.tokens * manifest.width.bytes();
+            if mmap.len() != expected {
+                return Err(Error::InvalidShard {
+                    path,
+                    reason: format!("expected {expected} bytes, found {}", mmap.len()),
+                });
+            }
+
+            shards.push(mmap);
+            offsets.push(tokens);
+            tokens += shard.tokens;
+        }
+
+        if tokens <= max_length {
+            return Err(Error::CorpusTooSmall {
+                tokens,
+                required: max_length + 1,
+            });
+        }
+
+        Ok(Self {
+            width: manifest.width,
+            shards,
+            offsets,
+            max_length,
+            stride,
+            len: (tokens - max_length).div_ceil(stride),
+            min_vocab_size: manifest.min_vocab_size,
+        })
+    }
+
+    /// The number of ids in each item's inputs and targets.
+    pub fn max_length(&self) -> usize {
+        self.max_length
+    }
+
+    /// Checks that a model built from `config` can take this dataset's items.
+    pub fn validate(&self, config: &GPTConfig) -> Result<()> {
+        validate_windows(self.max_length, self.min_vocab_size, config)
+    }
+
+    /// The ids at `range` in the stream, which may span several shards.
+    fn tokens(&self, range: Range<usize>) -> Vec<usize> {
+        let width = self.width.bytes();
+        let mut ids = Vec::with_capacity(range.len());
+
+        let mut position = range.start;
+        let mut shard = self.offsets.partition_point(|offset| *offset <= position) - 1;
+        while position < range.end {
+            let start = position - self.offsets[shard];
+            let end = (range.end - self.offsets[shard]).min(self.shards[shard].len() / width);
+
+            ids.extend(
+                self.shards[shard][start * width..end * width]
+                    .chunks_exact(width)
+                    .map(|bytes| self.width.decode(bytes)),
+            );
+
+            position += end - start;
+            shard += 1;
+        }
+
+        ids
+    }
+}
+
+impl Dataset<GPTDatasetItem> for ShardedDataset {
+    fn get(&self, index: usize) -> Option<GPTDatasetItem> {
+        if index >= self.len {
+            return None;
+        }
+
+        let start = index * self.stride;
+        let mut input_ids = self.tokens(start..start + self.max_length + 1);
+        let target_ids = input_ids[1..].to_vec();
+        input_ids.pop();
+
+        Some(GPTDatasetItem {
+            input_ids,
+            target_ids,
+            attention_mask: vec![true; self.max_length],
+            document_ids: None,
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use burn::{
+        backend::NdArray,
+        data::{dataloader::DataLoaderBuilder, dataset::Dataset},
+    };
+    use rstest::rstest;
+    use tempfile::TempDir;
+
+    use crate::{
+        error::Error,
+        listings::{
+            ch02::{
+                GPTDatasetBatcher, GPTDatasetV1, SimpleTokenizerV2,
+                corpus::Corpus,
+                shards::{ShardWriter, ShardedDataset, TokenWidth, pretokenize},
+            },
+            ch04::GPTConfig,
+        },
+    };
+
+    #[rstest]
+    #[case(4, 3)]
+    #[case(5, 1)]
+    #[case(16, 16)]
+    fn test_sharded_dataset_matches_in_memory(#[case] max_length: usize, #[case] stride: usize) {
+        let ids: Vec<usize> = (0..50).map(|i| (i * 7919) % 1000).collect();
+
+        let dir = TempDir::new().unwrap();
+        let mut writer = ShardWriter::new(dir.path(), TokenWidth::U16, 16).unwrap();
+        writer.write(&ids[..10]).unwrap();
+        writer.write(&ids[10..]).unwrap();
+        let manifest = writer.finish().unwrap();
+
+        assert_eq!(manifest.shards.len(), 4);
+        assert_eq!(manifest.tokens(), 50);
+        assert_eq!(manifest.min_vocab_size, ids.iter().max().unwrap() + 1);
+        assert_eq!(
+            fs::metadata(dir.path().join(&manifest.shards[0].file))
+                .unwrap()
+                .len(),
+            32
+        );
+
+        let sharded = ShardedDataset::open(dir.path(), max_length, stride).unwrap();
+        let in_memory = GPTDatasetV1::new_from_token_ids(ids, max_length, stride, None).unwrap();
+
+        assert_eq!(sharded.len(), in_memory.len());
+        for index in 0..sharded.len() {
+            let (a, b) = (sharded.get(index).unwrap(), in_memory.get(index).unwrap());
+            assert_eq!(a.input_ids, b.input_ids);
+            assert_eq!(a.target_ids, b.target_ids);
+            assert_eq!(a.attention_mask, b.attention_mask);
+        }
+        assert!(sharded.get(sharded.len()).is_none());
+    }
+
+    #[test]
+    fn test_pretokenize_corpus() {
+        let tokenizer = SimpleTokenizerV2::new(Corpus::Raw(
+            "the cat sat on the mat. the dog sat on the log.".to_string(),
+        ))
+        .unwrap();
+
+        let dir = TempDir::new().unwrap();
+        let docs = dir.path().join("docs");
+        fs::create_dir(&docs).unwrap();
+        fs::write(docs.join("1.txt"), "the cat sat").unwrap();
+        fs::write(docs.join("2.txt"), "the dog sat").unwrap();
+        let corpus = Corpus::Dir(docs);
+
+        let shards = dir.path().join("shards");
+        pretokenize(&corpus, &tokenizer, &shards, TokenWidth::U16).unwrap();
+
+        let sharded = ShardedDataset::open(&shards, 3, 1).unwrap();
+        let in_memory =
+            GPTDatasetV1::new_from_corpus(&corpus, Box::new(tokenizer), 3, 1, None).unwrap();
+        assert_eq!(sharded.len(), in_memory.len());
+        assert_eq!(
+            sharded.get(3).unwrap().target_ids,
+            in_memory.get(3).unwrap().target_ids
+        );
+        sharded.validate(&GPTConfig::new()).unwrap();
+
+        let dataloader = DataLoaderBuilder::<NdArray, _, _>::new(GPTDatasetBatcher::default())
+            .batch_size(2)
+            .build(sharded);
+        let batch = dataloader.iter().next().unwrap();
+        assert_eq!(batch.input_ids.shape().dims, [2, 3]);
+    }
+
+    #[test]
+    fn test_shard_widths() {
+        let dir = TempDir::new().unwrap();
+
+        let mut writer = ShardWriter::new(dir.path(), TokenWidth::U16, 16).unwrap();
+        assert!(matches!(
+            writer.write(&[1, 70000]),
+            Err(Error::InvalidShard { .. })
+        ));
+
+        assert_eq!(TokenWidth::for_vocab_size(50257), TokenWidth::U16);
+        assert_eq!(TokenWidth::for_vocab_size(100277), TokenWidth::U32);
+
+        let mut writer = ShardWriter::new(dir.path(), TokenWidth::U32, 16).unwrap();
+        writer.write(&[70000, 1, 100276, 2]).unwrap();
+        writer.finish().unwrap();
+
+        let dataset = ShardedDataset::open(dir.path(), 2, 1).unwrap();
+        assert_eq!(dataset.get(1).unwrap().target_ids, [100276, 2]);
+        assert!(matches!(
+            dataset.validate(&GPTConfig::new()),
+            Err(Error::UnknownId(100276))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_shards() {
+        let dir = TempDir::new().unwrap();
+        let mut writer = ShardWriter::new(dir.path(), TokenWidth::U16, 16).unwrap();
+        writer.write(&[1, 2, 3]).unwrap();
+        let manifest = writer.finish().unwrap();
+
+        assert!(matches!(
+            ShardedDataset::open(dir.path(), 3, 1),
+            Err(Error::CorpusTooSmall {
+                tokens: 3,
+                required: 4
+            })
+        ));
+
+        fs::write(dir.path().join(&manifest.shards[0].file), [1, 0, 2]).unwrap();
+        assert!(matches!(
+            ShardedDataset::open(dir.path(), 2, 1),
+            Err(Error::InvalidShard { .. })
+        ));
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    cache::{Artifact, Cache, verify},
+    error::{Error, Result},
+};
+
+/// Environment variable used to pick the [`CorpusSource`] at runtime.
+///
+/// Accepts `bundled` (the default), `cache:<dir>` or `fetch`.
+pub const CORPUS_SOURCE_ENV: &str = "LLMS_FROM_SCRATCH_CORPUS_SOURCE";
+
+/// Where the text behind a corpus URL is actually read from.
+///
+/// Corpora are always identified by their upstream URL, but only [`CorpusSource::Fetch`] ever
+/// touches the network.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum CorpusSource {
+    /// The copies checked into this repository's `data/` directory.
+    #[default]
+    Bundled,
+    /// A [`Cache`] rooted at the given directory, used offline.
+    CacheDir(PathBuf),
+    /// Download the corpus over HTTP through the [`Cache`] configured by [`Cache::from_env`]. Must
+    /// be opted into explicitly.
+    Fetch,
+}
+
+impl CorpusSource {
+    /// Reads the source from [`CORPUS_SOURCE_ENV`], falling back to [`CorpusSource::Bundled`].
+    pub fn from_env() -> Result<Self> {
+        match env::var(CORPUS_SOURCE_ENV) {
+            Ok(value) => value.parse(),
+            Err(env::VarError::NotPresent) => Ok(Self::default()),
+            Err(e) => Err(Error::Config(format!("{CORPUS_SOURCE_ENV}: {e}"))),
+        }
+    }
+
+    /// The directory bundled corpora are served from.
+    pub fn bundled_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("data")
+    }
+
+    pub fn read_to_string(&self, url: &str) -> Result<String> {
+        self.read_artifact(&Artifact::unpinned(url))
+    }
+
+    pub fn read_artifact(&self, artifact: &Artifact) -> Result<String> {
+        match self {
+            Self::Bundled => read_bundled(artifact),
+            Self::CacheDir(dir) => Cache::new(dir).offline(true).read_to_string(artifact),
+            Self::Fetch => Cache::from_env()?.read_to_string(artifact),
+        }
+    }
+}
+
+impl fmt::Display for CorpusSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bundled => write!(f, "bundled"),
+            Self::CacheDir(dir) => write!(f, "cache:{}", dir.display()),
+            Self::Fetch => write!(f, "fetch"),
+        }
+    }
+}
+
+impl FromStr for CorpusSource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bundled" => Ok(Self::Bundled),
+            "fetch" => Ok(Self::Fetch),
+            _ => match s.strip_prefix("cache:") {
+                Some(dir) if !dir.is_empty() => Ok(Self::CacheDir(PathBuf::from(dir))),
+                _ => Err(Error::InvalidCorpusSource(s.to_string())),
+            },
+        }
+    }
+}
+
+/// The last path segment of `url`, which is what bundled corpora are named after.
+fn file_name(url: &str) -> Result<&str> {
+    url.trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| Error::Config(format!("no file name in corpus url {url:?}")))
+}
+
+/// Bundled copies are held to the same pin as downloaded ones.
+fn read_bundled(artifact: &Artifact) -> Result<String> {
+    let path = CorpusSource::bundled_dir().join(file_name(artifact.url)?);
+    if let Some(expected) = artifact.sha256
+        && path.exists()
+    {
+        verify(&path, expected)?;
+    }
+
+    fs::read_to_string(&path).map_err(|source| Error::File { path, source })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use tempfile::TempDir;
+
+    use crate::{
+        cache::{Artifact, Cache},
+        error::Error,
+        listings::ch02::sources::CorpusSource,
+    };
+
+    #[test]
+    fn test_parse_corpus_source() {
+        // NB: never the network, unless asked for.
+        assert_eq!(CorpusSource::default(), CorpusSource::Bundled);
+        assert_eq!(
+            "bundled".parse::<CorpusSource>().unwrap(),
+            CorpusSource::Bundled
+        );
+        assert_eq!(
+            "fetch".parse::<CorpusSource>().unwrap(),
+            CorpusSource::Fetch
+        );
+        assert_eq!(
+            "cache:/var/cache/corpora".parse::<CorpusSource>().unwrap(),
+            CorpusSource::CacheDir(PathBuf::from("/var/cache/corpora"))
+        );
+
+        assert!(matches!(
+            "cache:".parse::<CorpusSource>(),
+            Err(Error::InvalidCorpusSource(_))
+        ));
+        assert!(matches!(
+            "github".parse::<CorpusSource>(),
+            Err(Error::InvalidCorpusSource(_))
+        ));
+    }
+
+    #[test]
+    fn test_read_from_cache_dir() {
+        let dir = TempDir::new().unwrap();
+        let path = Cache::new(dir.path())
+            .path(&Artifact::unpinned("https://example.com/texts/corpus.txt"));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "hello there").unwrap();
+
+        let source = CorpusSource::CacheDir(dir.path().to_path_buf());
+
+        assert_eq!(
+            source
+                .read_to_string("https://example.com/texts/corpus.txt")
+                .unwrap(),
+            "hello there"
+        );
+        assert!(
+            source
+                .read_to_string("https://example.com/texts/missing.txt")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_tampered_cached_corpus() {
+        let dir = TempDir::new().unwrap();
+        let artifact = Artifact::pinned(
+            "https://example.com/texts/corpus.txt",
+            // NB: the SHA-256 of "hello there".
+            "12998c017066eb0d2a70b94e6ed3192985855ce390f321bbdb832022888bd251",
+        );
+        let path = Cache::new(dir.path()).path(&artifact);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        let source = CorpusSource::CacheDir(dir.path().to_path_buf());
+        fs::write(&path, "hello there").unwrap();
+        assert_eq!(source.read_artifact(&artifact).unwrap(), "hello there");
+
+        fs::write(&path, "hello where").unwrap();
+        assert!(matches!(
+            source.read_artifact(&artifact),
+            Err(Error::ChecksumMismatch { .. })
+        ));
+    }
+}
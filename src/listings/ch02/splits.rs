@@ -0,0 +1,169 @@
+use burn::config::Config;
+use rand::{SeedableRng, rngs::StdRng, seq::SliceRandom};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// What a [`SplitConfig`]'s ratios are fractions of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SplitBy {
+    /// Cut the encoded corpus into consecutive runs of tokens, as the book does with its text.
+    Tokens,
+    /// Shuffle whole documents with the seed, then deal them out.
+    Documents,
+}
+
+/// How to divide a corpus into training, validation and test sets. Whatever the training and
+/// validation sets don't take goes to the test set.
+#[derive(Config, Debug)]
+pub struct SplitConfig {
+    #[config(default = 0.9)]
+    pub train_ratio: f64,
+    #[config(default = 0.1)]
+    pub validation_ratio: f64,
+    #[config(default = "SplitBy::Tokens")]
+    pub by: SplitBy,
+    /// Only used by [`SplitBy::Documents`].
+    #[config(default = 123)]
+    pub seed: u64,
+}
+
+/// One of something per split. There's only a test split if the config leaves room for one.
+#[derive(Clone, Debug)]
+pub struct Splits<T> {
+    pub train: T,
+    pub validation: T,
+    pub test: Option<T>,
+}
+
+impl<T> Splits<T> {
+    pub fn try_map<U>(self, mut f: impl FnMut(T) -> Result<U>) -> Result<Splits<U>> {
+        Ok(Splits {
+            train: f(self.train)?,
+            validation: f(self.validation)?,
+            test: self.test.map(f).transpose()?,
+        })
+    }
+}
+
+impl SplitConfig {
+    pub fn validate(&self) -> Result<()> {
+        let (train, validation) = (self.train_ratio, self.validation_ratio);
+        if !(train > 0.0 && validation > 0.0 && train + validation <= 1.0) {
+            return Err(Error::Config(format!(
+                "train_ratio ({train}) and validation_ratio ({validation}) must be positive and \
+                 sum to at most 1"
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn test_ratio(&self) -> f64 {
+        1.0 - self.train_ratio - self.validation_ratio
+    }
+
+    /// Cuts `items`, in order, into consecutive runs of the configured proportions.
+    pub fn cut<T>(&self, mut items: Vec<T>) -> Splits<Vec<T>> {
+        let len = items.len() as f64;
+        let train_end = (len * self.train_ratio).round() as usize;
+        // NB: any rounding error goes to the last split, so that nothing is lost.
+        let validation_end = if self.test_ratio() > f64::EPSILON {
+            train_end + (len * self.validation_ratio).round() as usize
+        } else {
+            items.len()
+        }
+        .min(items.len());
+
+        let test = items.split_off(validation_end);
+        let validation = items.split_off(train_end);
+
+        Splits {
+            train: items,
+            validation,
+            test: (self.test_ratio() > f64::EPSILON).then_some(test),
+        }
+    }
+
+    /// Like [`SplitConfig::cut`], but shuffles `items` with the seed first.
+    pub fn shuffle_and_cut<T>(&self, mut items: Vec<T>) -> Splits<Vec<T>> {
+        items.shuffle(&mut StdRng::seed_from_u64(self.seed));
+        self.cut(items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::{
+        error::Error,
+        listings::ch02::splits::{SplitBy, SplitConfig},
+    };
+
+    #[rstest]
+    #[case(0.9, 0.1, 90, 10, None)]
+    #[case(0.8, 0.1, 80, 10, Some(10))]
+    #[case(0.7, 0.2, 70, 20, Some(10))]
+    fn test_cut(
+        #[case] train_ratio: f64,
+        #[case] validation_ratio: f64,
+        #[case] train: usize,
+        #[case] validation: usize,
+        #[case] test: Option<usize>,
+    ) {
+        let config = SplitConfig::new()
+            .with_train_ratio(train_ratio)
+            .with_validation_ratio(validation_ratio);
+        config.validate().unwrap();
+
+        let splits = config.cut((0..100).collect::<Vec<_>>());
+        assert_eq!(splits.train.len(), train);
+        assert_eq!(splits.validation.len(), validation);
+        assert_eq!(splits.test.as_ref().map(Vec::len), test);
+        assert_eq!(splits.train.last(), Some(&(train - 1)));
+        assert_eq!(splits.validation[0], train);
+    }
+
+    #[test]
+    fn test_shuffle_and_cut() {
+        let config = SplitConfig::new()
+            .with_train_ratio(0.5)
+            .with_validation_ratio(0.25)
+            .with_by(SplitBy::Documents);
+        let items: Vec<_> = (0..20).collect();
+
+        let splits = config.shuffle_and_cut(items.clone());
+        assert_eq!(
+            config.shuffle_and_cut(items.clone()).train,
+            splits.train,
+            "the same seed should give the same split"
+        );
+        assert_ne!(
+            config.clone().with_seed(7).shuffle_and_cut(items).train,
+            splits.train
+        );
+
+        let mut all = [
+            splits.train,
+            splits.validation,
+            splits.test.unwrap_or_default(),
+        ]
+        .concat();
+        all.sort();
+        assert_eq!(all, (0..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_invalid_split() {
+        for (train, validation) in [(0.0, 0.1), (0.9, 0.0), (0.9, 0.2)] {
+            assert!(matches!(
+                SplitConfig::new()
+                    .with_train_ratio(train)
+                    .with_validation_ratio(validation)
+                    .validate(),
+                Err(Error::Config(_))
+            ));
+        }
+    }
+}
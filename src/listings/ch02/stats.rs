@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::{
+    error::{Error, Result},
+    listings::ch02::{
+        Tokenizer,
+        corpus::{Corpus, DOCUMENT_SEPARATOR},
+        encode_documents,
+    },
+};
+
+/// What a [`Corpus`] looks like once encoded by a [`Tokenizer`], from [`corpus_stats`]. Counts
+/// cover the whole token stream, including the separators between documents.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CorpusStats {
+    pub documents: usize,
+    pub bytes: usize,
+    pub tokens: usize,
+    pub bytes_per_token: f64,
+    pub vocab_size: usize,
+    /// Distinct ids in the corpus.
+    pub distinct_tokens: usize,
+    /// The share of the vocabulary that appears in the corpus.
+    pub vocab_coverage: f64,
+    /// The share of tokens that are `<|unk|>`, for tokenizers that have one.
+    pub unknown_rate: Option<f64>,
+    pub max_length: usize,
+    pub stride: usize,
+    /// How many items [`GPTDatasetV1`](super::GPTDatasetV1) would make of the corpus, without
+    /// padding.
+    pub windows: usize,
+    /// The most frequent tokens, most frequent first.
+    pub top_tokens: Vec<TokenCount>,
+    /// How many distinct tokens occur between `2^i` and `2^(i + 1) - 1` times, for each `i`.
+    pub frequency_histogram: Vec<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct TokenCount {
+    pub id: usize,
+    /// Lossily decoded, since byte-level tokens needn't be valid UTF-8 on their own.
+    pub token: String,
+    pub count: usize,
+}
+
+/// Encodes `corpus` the same way [`GPTDatasetV1::new_from_corpus`](super::GPTDatasetV1) does,
+/// and reports on the result, with the `top` most frequent tokens.
+pub fn corpus_stats(
+    corpus: &Corpus,
+    tokenizer: &dyn Tokenizer,
+    max_length: usize,
+    stride: usize,
+    top: usize,
+) -> Result<CorpusStats> {
+    if max_length == 0 || stride == 0 {
+        return Err(Error::Config(format!(
+            "max_length ({max_length}) and stride ({stride}) must both be positive"
+        )));
+    }
+
+    let mut documents: usize = 0;
+    let mut bytes: usize = 0;
+    let mut counts: HashMap<usize, usize> = HashMap::new();
+    let texts = corpus.documents()?.inspect(|document| {
+        if let Ok(document) = document {
+            documents += 1;
+            bytes += document.len();
+        }
+    });
+    encode_documents(texts, tokenizer, DOCUMENT_SEPARATOR, |separator, ids| {
+        for id in separator.iter().chain(ids) {
+            *counts.entry(*id).or_default() += 1;
+        }
+        Ok(())
+    })?;
+    bytes += DOCUMENT_SEPARATOR.len() * documents.saturating_sub(1);
+
+    let tokens: usize = counts.values().sum();
+    let vocab_size = tokenizer.vocab_size();
+    let ratio = |n: usize, d: usize| if d == 0 { 0.0 } else { n as f64 / d as f64 };
+
+    let mut frequency_histogram = Vec::new();
+    for count in counts.values() {
+        let bucket = count.ilog2() as usize;
+        if frequency_histogram.len() <= bucket {
+            frequency_histogram.resize(bucket + 1, 0);
+        }
+        frequency_histogram[bucket] += 1;
+    }
+
+    let mut by_count: Vec<(usize, usize)> = counts.iter().map(|(id, n)| (*id, *n)).collect();
+    by_count.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    let top_tokens = by_count
+        .into_iter()
+        .take(top)
+        .map(|(id, count)| {
+            Ok(TokenCount {
+                id,
+                token: String::from_utf8_lossy(&tokenizer.decode_bytes(id)?).into_owned(),
+                count,
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(CorpusStats {
+        documents,
+        bytes,
+        tokens,
+        bytes_per_token: ratio(bytes, tokens),
+        vocab_size,
+        distinct_tokens: counts.len(),
+        vocab_coverage: ratio(counts.len(), vocab_size),
+        unknown_rate: tokenizer
+            .unknown_id()
+            .map(|id| ratio(counts.get(&id).copied().unwrap_or(0), tokens)),
+        max_length,
+        stride,
+        windows: tokens.saturating_sub(max_length).div_ceil(stride),
+        top_tokens,
+        frequency_histogram,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::data::dataset::Dataset;
+
+    use crate::listings::ch02::{
+        GPTDatasetV1, SimpleTokenizerV2,
+        corpus::Corpus,
+        stats::corpus_stats,
+        tokenizers::{Encoding, SpecialTokenPolicy, TiktokenTokenizer},
+    };
+
+    #[test]
+    fn test_simple_tokenizer_stats() {
+        let tokenizer =
+            SimpleTokenizerV2::new(Corpus::Raw("the cat sat on the mat.".to_string())).unwrap();
+        let corpus = Corpus::Raw("the cat sat on the dog's mat.".to_string());
+
+        let stats = corpus_stats(&corpus, &tokenizer, 4, 2, 2).unwrap();
+        assert_eq!(stats.documents, 1);
+        assert_eq!(stats.bytes, 29);
+        // "dog", "'" and "s" are all unknown.
+        assert_eq!(stats.tokens, 10);
+        assert_eq!(stats.unknown_rate, Some(0.3));
+        assert_eq!(stats.vocab_size, 8);
+        assert_eq!(stats.distinct_tokens, 7);
+        assert_eq!(stats.vocab_coverage, 0.875);
+        assert_eq!(stats.top_tokens[0].token, "<|unk|>");
+        assert_eq!(stats.top_tokens[0].count, 3);
+        assert_eq!(stats.top_tokens[1].token, "the");
+        // Five tokens once, one twice and one three times.
+        assert_eq!(stats.frequency_histogram, [5, 2]);
+
+        let dataset =
+            GPTDatasetV1::new_from_corpus(&corpus, Box::new(tokenizer), 4, 2, None).unwrap();
+        assert_eq!(stats.windows, dataset.len());
+    }
+
+    #[test]
+    fn test_tiktoken_stats() {
+        let tokenizer =
+            TiktokenTokenizer::new(Encoding::Gpt2, SpecialTokenPolicy::allow(["<|endoftext|>"]))
+                .unwrap();
+        let corpus = Corpus::Raw("Hello, world. Hello again.".to_string());
+
+        let stats = corpus_stats(&corpus, &tokenizer, 2, 1, 1).unwrap();
+        assert_eq!(stats.tokens, 7);
+        assert_eq!(stats.unknown_rate, None);
+        assert_eq!(stats.vocab_size, 50257);
+        assert_eq!(stats.windows, 5);
+        // The second "Hello" is " Hello", a different token.
+        assert_eq!(stats.top_tokens[0].token, ".");
+        assert_eq!(stats.top_tokens[0].count, 2);
+
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["tokens"], 7);
+        assert!(json["unknown_rate"].is_null());
+    }
+}
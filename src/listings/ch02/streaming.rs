@@ -0,0 +1,686 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader, Seek, SeekFrom},
+    iter,
+    ops::Range,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use burn::{
+    data::dataloader::{DataLoader, DataLoaderIterator, Progress, batcher::Batcher},
+    prelude::Backend,
+};
+use rand::{Rng, RngCore, SeedableRng, rngs::StdRng};
+
+use crate::{
+    error::{Error, Result},
+    listings::ch02::{
+        GPTDatasetItem, Tokenizer,
+        corpus::{Corpus, DOCUMENT_SEPARATOR, Document, parse_line, read_file},
+    },
+};
+
+/// The same windows as [`GPTDatasetV1::new_from_corpus`](super::GPTDatasetV1), but read, encoded
+/// and windowed a document at a time as they're iterated, so only the current document and the
+/// shuffle buffer are ever held in memory.
+pub struct StreamingDataset {
+    sources: Vec<Source>,
+    tokenizer: Box<dyn Tokenizer>,
+    separator: Vec<usize>,
+    max_length: usize,
+    stride: usize,
+    shuffle_buffer: usize,
+}
+
+impl StreamingDataset {
+    /// Opens every file of `corpus` to check it can be read, without reading it. A corpus from a
+    /// URL or stdin is read in here, since it can't be read again on every pass.
+    pub fn new(
+        corpus: Corpus,
+        tokenizer: Box<dyn Tokenizer>,
+        max_length: usize,
+        stride: usize,
+    ) -> Result<Self> {
+        if max_length == 0 || stride == 0 {
+            return Err(Error::Config(format!(
+                "max_length ({max_length}) and stride ({stride}) must both be positive"
+            )));
+        }
+
+        Ok(Self {
+            sources: Source::open(corpus)?,
+            separator: tokenizer.encode(DOCUMENT_SEPARATOR.to_string())?,
+            tokenizer,
+            max_length,
+            stride,
+            shuffle_buffer: 0,
+        })
+    }
+
+    /// Shuffles items within a sliding buffer of `size` items. Items can only move around within
+    /// roughly that distance of where they'd otherwise be, so it should span several documents.
+    pub fn shuffle_buffer(mut self, size: usize) -> Self {
+        self.shuffle_buffer = size;
+        self
+    }
+
+    /// The number of ids in each item's inputs and targets.
+    pub fn max_length(&self) -> usize {
+        self.max_length
+    }
+
+    /// The corpus's size in bytes.
+    fn size(&self) -> usize {
+        self.sources.iter().map(Source::len).sum()
+    }
+
+    /// The documents that start within `range` of the corpus's bytes, or all of them. Only those
+    /// are read.
+    fn documents(&self, range: Option<Range<usize>>) -> impl Iterator<Item = Document> + '_ {
+        let range = range.unwrap_or(0..usize::MAX);
+        let mut offset = 0;
+        self.sources.iter().flat_map(move |source| {
+            let within = range.start.saturating_sub(offset)..range.end.saturating_sub(offset);
+            offset += source.len();
+            source.documents(within)
+        })
+    }
+
+    /// The items of the documents within `range`, or of every document, in order. They're shuffled
+    /// with `rng` if there's a shuffle buffer.
+    fn items(
+        &self,
+        range: Option<Range<usize>>,
+        rng: Option<StdRng>,
+    ) -> Box<dyn Iterator<Item = Result<GPTDatasetItem>> + '_> {
+        let windows = Windows {
+            dataset: self,
+            documents: Box::new(self.documents(range)),
+            first: true,
+            tokens: VecDeque::new(),
+            skip: 0,
+        };
+
+        match rng {
+            Some(rng) if self.shuffle_buffer > 1 => Box::new(Shuffled {
+                inner: windows,
+                buffer: Vec::with_capacity(self.shuffle_buffer),
+                size: self.shuffle_buffer,
+                rng,
+            }),
+            _ => Box::new(windows),
+        }
+    }
+}
+
+/// Part of a [`StreamingDataset`]'s corpus, checked and sized when the dataset's built.
+enum Source {
+    Text(String),
+    /// A file that's one whole document.
+    File {
+        path: PathBuf,
+        len: usize,
+    },
+    /// A JSON Lines file with one document per line.
+    Jsonl {
+        path: PathBuf,
+        field: String,
+        len: usize,
+    },
+}
+
+impl Source {
+    fn open(corpus: Corpus) -> Result<Vec<Self>> {
+        match corpus {
+            Corpus::File(_) | Corpus::Dir(_) | Corpus::Glob(_) => corpus
+                .files()?
+                .map(|path| {
+                    let path = path?;
+                    Ok(Source::File {
+                        len: file_len(&path)?,
+                        path,
+                    })
+                })
+                .collect(),
+            Corpus::Jsonl { path, field } => Ok(vec![Source::Jsonl {
+                len: file_len(&path)?,
+                path,
+                field,
+            }]),
+            Corpus::Raw(_) | Corpus::Url(_) | Corpus::Stdin => {
+                Ok(vec![Source::Text(corpus.text()?)])
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Source::Text(text) => text.len(),
+            Source::File { len, .. } | Source::Jsonl { len, .. } => *len,
+        }
+    }
+
+    /// The documents whose first byte is within `range` of this source's.
+    fn documents(&self, range: Range<usize>) -> Box<dyn Iterator<Item = Document> + '_> {
+        match self {
+            Source::Text(text) if range.contains(&0) => Box::new(iter::once(Ok(text.clone()))),
+            Source::File { path, .. } if range.contains(&0) => {
+                Box::new(iter::once_with(|| read_file(path)))
+            }
+            Source::Jsonl { path, field, len } if range.start < *len && !range.is_empty() => {
+                match lines(path, range) {
+                    Ok(lines) => Box::new(lines.filter_map(|line| match line {
+                        Ok((_, line)) if line.trim().is_empty() => None,
+                        Ok((start, line)) => Some(parse_line(&line, field, || {
+                            format!("{} at byte {start}", path.display())
+                        })),
+                        Err(e) => Some(Err(e)),
+                    })),
+                    Err(e) => Box::new(iter::once(Err(e))),
+                }
+            }
+            _ => Box::new(iter::empty()),
+        }
+    }
+}
+
+fn file_len(path: &PathBuf) -> Result<usize> {
+    File::open(path)
+        .and_then(|file| file.metadata())
+        .map(|metadata| metadata.len() as usize)
+        .map_err(|source| Error::File {
+            path: path.clone(),
+            source,
+        })
+}
+
+/// The lines of the file at `path` that start within `range` of its bytes, with where they start.
+fn lines(
+    path: &PathBuf,
+    range: Range<usize>,
+) -> Result<impl Iterator<Item = Result<(usize, String)>> + use<>> {
+    let error = |source| Error::File {
+        path: path.clone(),
+        source,
+    };
+    let mut reader = BufReader::new(File::open(path).map_err(error)?);
+
+    // NB: a line belongs to the range its first byte is in, so the end of the one before is
+    // skipped. Reading from the byte before the range means a line starting right on it is kept.
+    let mut offset = range.start.saturating_sub(1);
+    reader.seek(SeekFrom::Start(offset as u64)).map_err(error)?;
+    if range.start > 0 {
+        offset += reader.read_until(b'\n', &mut Vec::new()).map_err(error)?;
+    }
+
+    let path = path.clone();
+    Ok(iter::from_fn(move || {
+        if offset >= range.end {
+            return None;
+        }
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => None,
+            Ok(read) => {
+                let start = offset;
+                offset += read;
+                Some(Ok((start, line)))
+            }
+            Err(source) => {
+                offset = range.end;
+                Some(Err(Error::File {
+                    path: path.clone(),
+                    source,
+                }))
+            }
+        }
+    }))
+}
+
+struct Windows<'a> {
+    dataset: &'a StreamingDataset,
+    documents: Box<dyn Iterator<Item = Document> + 'a>,
+    first: bool,
+    /// Ids read from the corpus, starting at the next window.
+    tokens: VecDeque<usize>,
+    /// How many more ids to throw away before the next window, when the stride is longer than
+    /// what's been read.
+    skip: usize,
+}
+
+impl Windows<'_> {
+    fn push(&mut self, ids: &[usize]) {
+        let skipped = self.skip.min(ids.len());
+        self.skip -= skipped;
+        self.tokens.extend(&ids[skipped..]);
+    }
+}
+
+impl Iterator for Windows<'_> {
+    type Item = Result<GPTDatasetItem>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (max_length, stride) = (self.dataset.max_length, self.dataset.stride);
+
+        // NB: a window needs one more id than its length, for the last target.
+        while self.tokens.len() <= max_length {
+            let document = match self.documents.next()? {
+                Ok(document) => document,
+                Err(e) => return Some(Err(e)),
+            };
+            let ids = match self.dataset.tokenizer.encode_parallel(&document) {
+                Ok(ids) => ids,
+                Err(e) => return Some(Err(e)),
+            };
+
+            if !self.first {
+                let dataset = self.dataset;
+                self.push(&dataset.separator);
+            }
+            self.first = false;
+            self.push(&ids);
+        }
+
+        let window: Vec<usize> = self.tokens.range(..=max_length).copied().collect();
+        let drained = stride.min(self.tokens.len());
+        self.tokens.drain(..drained);
+        self.skip = stride - drained;
+
+        Some(Ok(GPTDatasetItem {
+            input_ids: window[..max_length].to_vec(),
+            target_ids: window[1..].to_vec(),
+            attention_mask: vec![true; max_length],
+            document_ids: None,
+        }))
+    }
+}
+
+/// Yields a random item out of a buffer of `size`, refilling it from `inner` as it goes.
+struct Shuffled<I> {
+    inner: I,
+    buffer: Vec<GPTDatasetItem>,
+    size: usize,
+    rng: StdRng,
+}
+
+impl<I: Iterator<Item = Result<GPTDatasetItem>>> Iterator for Shuffled<I> {
+    type Item = Result<GPTDatasetItem>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.buffer.len() < self.size {
+            match self.inner.next() {
+                Some(Ok(item)) => self.buffer.push(item),
+                Some(Err(e)) => return Some(Err(e)),
+                None => break,
+            }
+        }
+
+        if self.buffer.is_empty() {
+            return None;
+        }
+        let index = self.rng.random_range(0..self.buffer.len());
+        Some(Ok(self.buffer.swap_remove(index)))
+    }
+}
+
+/// Batches a [`StreamingDataset`] for Burn. Since the stream's length isn't known until it's been
+/// read, [`DataLoader::num_items`] is the corpus's size in bytes and the progress total is 0.
+/// Slicing takes the documents that start within that range of bytes, so the loader can be split
+/// across devices with each only reading its own documents. Windows don't span two slices.
+///
+/// Batches are `Err` if reading or encoding the corpus fails part way through, after which the
+/// pass stops rather than quietly training on less of it.
+pub(super) struct StreamingDataLoader<B: Backend, O> {
+    dataset: Arc<StreamingDataset>,
+    batcher: Arc<dyn Batcher<B, GPTDatasetItem, O>>,
+    batch_size: usize,
+    drop_last: bool,
+    device: B::Device,
+    /// Seeds each pass's shuffle buffer, so that passes are shuffled differently.
+    rng: Option<Arc<Mutex<StdRng>>>,
+    /// Only the documents starting within this range of the corpus's bytes, if the loader has
+    /// been sliced.
+    range: Option<Range<usize>>,
+}
+
+impl<B: Backend, O> StreamingDataLoader<B, O> {
+    pub(super) fn new(
+        dataset: StreamingDataset,
+        batcher: Arc<dyn Batcher<B, GPTDatasetItem, O>>,
+        batch_size: usize,
+        drop_last: bool,
+        shuffle: Option<u64>,
+    ) -> Self {
+        Self {
+            dataset: Arc::new(dataset),
+            batcher,
+            batch_size,
+            drop_last,
+            device: B::Device::default(),
+            rng: shuffle.map(|seed| Arc::new(Mutex::new(StdRng::seed_from_u64(seed)))),
+            range: None,
+        }
+    }
+}
+
+impl<B: Backend, O> Clone for StreamingDataLoader<B, O> {
+    fn clone(&self) -> Self {
+        Self {
+            dataset: self.dataset.clone(),
+            batcher: self.batcher.clone(),
+            batch_size: self.batch_size,
+            drop_last: self.drop_last,
+            device: self.device.clone(),
+            rng: self.rng.clone(),
+            range: self.range.clone(),
+        }
+    }
+}
+
+impl<B: Backend, O: 'static> DataLoader<B, Result<O>> for StreamingDataLoader<B, O> {
+    fn iter<'a>(&'a self) -> Box<dyn DataLoaderIterator<Result<O>> + 'a> {
+        let rng = self.rng.as_ref().map(|rng| {
+            let seed = rng.lock().expect("the rng lock was poisoned").next_u64();
+            StdRng::seed_from_u64(seed)
+        });
+
+        Box::new(StreamingDataLoaderIterator {
+            loader: self,
+            items: self.dataset.items(self.range.clone(), rng),
+            processed: 0,
+        })
+    }
+
+    fn num_items(&self) -> usize {
+        self.range
+            .as_ref()
+            .map_or_else(|| self.dataset.size(), |range| range.len())
+    }
+
+    fn to_device(&self, device: &B::Device) -> Arc<dyn DataLoader<B, Result<O>>> {
+        Arc::new(Self {
+            device: device.clone(),
+            ..self.clone()
+        })
+    }
+
+    fn slice(&self, start: usize, end: usize) -> Arc<dyn DataLoader<B, Result<O>>> {
+        let offset = self.range.as_ref().map_or(0, |range| range.start);
+        let end = end.min(self.num_items());
+        Arc::new(Self {
+            range: Some(offset + start.min(end)..offset + end),
+            ..self.clone()
+        })
+    }
+}
+
+struct StreamingDataLoaderIterator<'a, B: Backend, O> {
+    loader: &'a StreamingDataLoader<B, O>,
+    items: Box<dyn Iterator<Item = Result<GPTDatasetItem>> + 'a>,
+    processed: usize,
+}
+
+impl<B: Backend, O> Iterator for StreamingDataLoaderIterator<'_, B, O> {
+    type Item = Result<O>;
+
+    fn next(&mut self) -> Option<Result<O>> {
+        let items = match self
+            .items
+            .by_ref()
+            .take(self.loader.batch_size)
+            .collect::<Result<Vec<_>>>()
+        {
+            Ok(items) => items,
+            Err(e) => {
+                self.items = Box::new(iter::empty());
+                return Some(Err(e));
+            }
+        };
+
+        if items.is_empty() || (self.loader.drop_last && items.len() < self.loader.batch_size) {
+            return None;
+        }
+        self.processed += items.len();
+
+        Some(Ok(self.loader.batcher.batch(items, &self.loader.device)))
+    }
+}
+
+impl<B: Backend, O> DataLoaderIterator<Result<O>> for StreamingDataLoaderIterator<'_, B, O> {
+    fn progress(&self) -> Progress {
+        Progress::new(self.processed, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Arc};
+
+    use burn::{
+        backend::{NdArray, ndarray::NdArrayDevice},
+        data::{
+            dataloader::{DataLoader, split::split_dataloader},
+            dataset::Dataset,
+        },
+    };
+    use rand::{SeedableRng, rngs::StdRng};
+    use rstest::rstest;
+    use tempfile::TempDir;
+
+    use crate::{
+        error::{Error, Result},
+        listings::ch02::{
+            GPTDatasetBatch, GPTDatasetBatcher, GPTDatasetV1, SimpleTokenizerV2,
+            corpus::{Corpus, DOCUMENT_SEPARATOR},
+            streaming::{StreamingDataLoader, StreamingDataset},
+        },
+    };
+
+    const TEXT: &str = "the cat sat on the mat. the dog sat on the log.";
+    const DOCUMENTS: [&str; 3] = ["the cat sat on the mat.", "the dog", "sat on the log."];
+
+    type Loader = Arc<dyn DataLoader<NdArray, Result<GPTDatasetBatch<NdArray>>>>;
+
+    fn corpus(dir: &TempDir) -> Corpus {
+        for (i, text) in DOCUMENTS.iter().enumerate() {
+            fs::write(dir.path().join(format!("{i}.txt")), text).unwrap();
+        }
+        Corpus::Dir(dir.path().to_path_buf())
+    }
+
+    fn tokenizer() -> Box<SimpleTokenizerV2> {
+        Box::new(SimpleTokenizerV2::new(Corpus::Raw(TEXT.to_string())).unwrap())
+    }
+
+    fn loader(corpus: Corpus) -> Loader {
+        let dataset = StreamingDataset::new(corpus, tokenizer(), 2, 1).unwrap();
+        Arc::new(StreamingDataLoader::new(
+            dataset,
+            Arc::new(GPTDatasetBatcher::default()),
+            4,
+            false,
+            None,
+        ))
+    }
+
+    fn rows(loader: &Loader) -> Vec<Vec<i64>> {
+        loader
+            .iter()
+            .flat_map(|batch| {
+                batch
+                    .unwrap()
+                    .input_ids
+                    .into_data()
+                    .to_vec::<i64>()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>()
+            .chunks(2)
+            .map(<[i64]>::to_vec)
+            .collect()
+    }
+
+    #[rstest]
+    #[case(3, 1)]
+    #[case(4, 3)]
+    #[case(2, 7)]
+    fn test_streaming_matches_in_memory(#[case] max_length: usize, #[case] stride: usize) {
+        let dir = TempDir::new().unwrap();
+
+        let streaming =
+            StreamingDataset::new(corpus(&dir), tokenizer(), max_length, stride).unwrap();
+        let in_memory =
+            GPTDatasetV1::new_from_corpus(&corpus(&dir), tokenizer(), max_length, stride, None)
+                .unwrap();
+
+        let items = streaming
+            .items(None, None)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(items.len(), in_memory.len());
+        for (index, item) in items.into_iter().enumerate() {
+            let expected = in_memory.get(index).unwrap();
+            assert_eq!(item.input_ids, expected.input_ids);
+            assert_eq!(item.target_ids, expected.target_ids);
+        }
+    }
+
+    #[test]
+    fn test_shuffle_buffer() {
+        let dir = TempDir::new().unwrap();
+        let streaming = StreamingDataset::new(corpus(&dir), tokenizer(), 2, 1)
+            .unwrap()
+            .shuffle_buffer(4);
+
+        let targets = |rng| {
+            streaming
+                .items(None, rng)
+                .map(|item| item.unwrap().target_ids)
+                .collect::<Vec<_>>()
+        };
+        let ordered = targets(None);
+        let shuffled = targets(Some(StdRng::seed_from_u64(0)));
+        assert_ne!(shuffled, ordered);
+        assert_eq!(shuffled, targets(Some(StdRng::seed_from_u64(0))));
+
+        let mut sorted = shuffled.clone();
+        sorted.sort();
+        let mut expected = ordered.clone();
+        expected.sort();
+        assert_eq!(sorted, expected);
+    }
+
+    #[test]
+    fn test_streaming_dataloader() {
+        let dir = TempDir::new().unwrap();
+        let dataset = StreamingDataset::new(corpus(&dir), tokenizer(), 2, 1)
+            .unwrap()
+            .shuffle_buffer(4);
+        // 16 ids with separators, so 14 windows.
+        let loader = StreamingDataLoader::<NdArray, _>::new(
+            dataset,
+            Arc::new(GPTDatasetBatcher::default()),
+            4,
+            true,
+            Some(0),
+        );
+
+        let batches = loader.iter().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(batches.len(), 3);
+        assert_eq!(batches[0].input_ids.dims(), [4, 2]);
+
+        assert!(matches!(
+            StreamingDataset::new(Corpus::Raw(TEXT.to_string()), tokenizer(), 0, 1),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_streaming_errors() {
+        let dir = TempDir::new().unwrap();
+        assert!(matches!(
+            StreamingDataset::new(
+                Corpus::File(dir.path().join("missing.txt")),
+                tokenizer(),
+                2,
+                1
+            ),
+            Err(Error::File { .. })
+        ));
+
+        // The first document makes 5 windows, and then the second is gone.
+        let loader = loader(corpus(&dir));
+        fs::remove_file(dir.path().join("1.txt")).unwrap();
+        let batches: Vec<_> = loader.iter().collect();
+        assert_eq!(batches.len(), 2);
+        assert!(batches[0].is_ok());
+        assert!(matches!(batches[1], Err(Error::File { .. })));
+    }
+
+    #[test]
+    fn test_split_streaming_dataloader() {
+        let dir = TempDir::new().unwrap();
+        let loader = loader(corpus(&dir));
+        // The documents start at bytes 0, 23 and 30 of 45.
+        assert_eq!(loader.num_items(), 45);
+
+        let devices = [NdArrayDevice::Cpu, NdArrayDevice::Cpu];
+        let halves = split_dataloader(loader.clone(), &devices);
+        assert_eq!(halves[0].num_items() + halves[1].num_items(), 45);
+        assert_eq!(
+            rows(&halves[0]),
+            rows(&self::loader(Corpus::Raw(DOCUMENTS[0].to_string())))
+        );
+        assert_eq!(
+            rows(&halves[1]),
+            rows(&self::loader(Corpus::Raw(
+                DOCUMENTS[1..].join(DOCUMENT_SEPARATOR)
+            )))
+        );
+
+        // Bytes 22 to 33 and 33 to 45, so the second quarter starts no documents.
+        let quarters = split_dataloader(halves[1].clone(), &devices);
+        assert_eq!(rows(&quarters[0]), rows(&halves[1]));
+        assert!(rows(&quarters[1]).is_empty());
+    }
+
+    #[test]
+    fn test_split_jsonl() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("corpus.jsonl");
+        fs::write(
+            &path,
+            "{\"text\": \"the cat\"}\n\n{\"text\": \"sat on\"}\n{\"text\": \"the mat.\"}",
+        )
+        .unwrap();
+        let dataset = StreamingDataset::new(
+            Corpus::Jsonl {
+                path,
+                field: "text".to_string(),
+            },
+            tokenizer(),
+            2,
+            1,
+        )
+        .unwrap();
+
+        let documents = |range| {
+            dataset
+                .documents(range)
+                .collect::<Result<Vec<_>>>()
+                .unwrap()
+        };
+        let all = documents(None);
+        assert_eq!(all, ["the cat", "sat on", "the mat."]);
+
+        // Wherever the split is, each line is read by exactly one side of it.
+        let size = dataset.size();
+        for split in 0..=size {
+            let mut halves = documents(Some(0..split));
+            halves.extend(documents(Some(split..size)));
+            assert_eq!(halves, all, "split at byte {split}");
+        }
+    }
+}
@@ -0,0 +1,463 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::LazyLock,
+};
+
+use regex::Regex;
+
+use crate::{
+    error::{Error, Result},
+    listings::ch02::{
+        Tokenizer,
+        corpus::Corpus,
+        tokenizers::serialization::{TokenizerFile, TokenizerModel, bpe_vocab},
+    },
+};
+
+/// GPT-2's pre-tokenization pattern, minus the `\s+(?!\S)` lookahead that `regex` can't express.
+static PRE_TOKENIZE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"'(?:[sdmt]|ll|ve|re)| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+").unwrap()
+});
+
+/// Number of single-byte tokens every byte-level vocabulary starts with.
+pub(crate) const BYTE_TOKENS: usize = 256;
+
+/// GPT-2's printable stand-ins for raw bytes, which keep whitespace and control bytes visible in
+/// serialized vocabularies.
+static BYTE_TO_CHAR: LazyLock<[char; 256]> = LazyLock::new(|| {
+    let mut table = ['\0'; 256];
+    let mut n = 0;
+
+    for b in 0..=u8::MAX {
+        let printable = matches!(b, b'!'..=b'~' | 0xA1..=0xAC | 0xAE..=0xFF);
+        table[b as usize] = if printable {
+            char::from(b)
+        } else {
+            n += 1;
+            char::from_u32(255 + n).unwrap()
+        };
+    }
+
+    table
+});
+
+pub(crate) fn byte_to_char(b: u8) -> char {
+    BYTE_TO_CHAR[b as usize]
+}
+
+type Pair = (usize, usize);
+
+/// Learns byte-level BPE merges from a [`Corpus`].
+#[derive(Clone, Debug)]
+pub struct BPETrainer {
+    vocab_size: usize,
+    special_tokens: Vec<String>,
+}
+
+impl BPETrainer {
+    /// `vocab_size` counts the 256 byte tokens, the learned merges and any special tokens.
+    pub fn new(vocab_size: usize) -> Self {
+        Self {
+            vocab_size,
+            special_tokens: Vec::new(),
+        }
+    }
+
+    /// Tokens such as `<|endoftext|>` that are never split, and get the last ids in the
+    /// vocabulary.
+    pub fn special_tokens(mut self, special_tokens: Vec<String>) -> Self {
+        self.special_tokens = special_tokens;
+        self
+    }
+
+    pub fn train(&self, corpus: &Corpus) -> Result<BPETokenizer> {
+        let num_merges = self
+            .vocab_size
+            .checked_sub(BYTE_TOKENS + self.special_tokens.len())
+            .ok_or_else(|| {
+                Error::InvalidTokenizer(format!(
+                    "vocab size {} can't fit {BYTE_TOKENS} byte tokens and {} special tokens",
+                    self.vocab_size,
+                    self.special_tokens.len()
+                ))
+            })?;
+
+        let special_regex = special_tokens_regex(&self.special_tokens);
+
+        let mut word_counts: HashMap<String, usize> = HashMap::new();
+        for document in corpus.documents()? {
+            let document = document?;
+            for segment in split_special(special_regex.as_ref(), &document) {
+                if let Segment::Text(text) = segment {
+                    for word in PRE_TOKENIZE_REGEX.find_iter(text) {
+                        *word_counts.entry(word.as_str().to_string()).or_default() += 1;
+                    }
+                }
+            }
+        }
+
+        let mut words: Vec<(Vec<usize>, usize)> = word_counts
+            .into_iter()
+            .map(|(word, count)| (word.bytes().map(usize::from).collect(), count))
+            .collect();
+
+        let mut pair_counts: HashMap<Pair, usize> = HashMap::new();
+        let mut pair_words: HashMap<Pair, HashSet<usize>> = HashMap::new();
+        for (i, (ids, count)) in words.iter().enumerate() {
+            for pair in ids.windows(2) {
+                let pair = (pair[0], pair[1]);
+                *pair_counts.entry(pair).or_default() += count;
+                pair_words.entry(pair).or_default().insert(i);
+            }
+        }
+
+        let mut merges = Vec::with_capacity(num_merges);
+        while merges.len() < num_merges {
+            // NB: Ties are broken towards the smallest pair so that training is deterministic.
+            let Some((&pair, _)) = pair_counts
+                .iter()
+                .filter(|(_, count)| **count > 0)
+                .max_by(|(a, a_count), (b, b_count)| a_count.cmp(b_count).then(b.cmp(a)))
+            else {
+                break;
+            };
+
+            let new_id = BYTE_TOKENS + merges.len();
+            merges.push(pair);
+
+            for i in pair_words.remove(&pair).unwrap_or_default() {
+                let (ids, count) = &mut words[i];
+
+                for old in ids.windows(2) {
+                    *pair_counts.get_mut(&(old[0], old[1])).unwrap() -= *count;
+                }
+
+                *ids = merge(ids, pair, new_id);
+
+                for new in ids.windows(2) {
+                    let new = (new[0], new[1]);
+                    *pair_counts.entry(new).or_default() += *count;
+                    pair_words.entry(new).or_default().insert(i);
+                }
+            }
+            pair_counts.remove(&pair);
+        }
+
+        Ok(BPETokenizer::new(merges, self.special_tokens.clone()))
+    }
+}
+
+/// A byte-level BPE tokenizer over merges learned by [`BPETrainer`].
+///
+/// Ids `0..256` are raw bytes, the `i`th merge produces id `256 + i`, and special tokens come
+/// last.
+#[derive(Clone, Debug)]
+pub struct BPETokenizer {
+    merges: Vec<Pair>,
+    ranks: HashMap<Pair, usize>,
+    vocab: Vec<Vec<u8>>,
+    special_tokens: HashMap<String, usize>,
+    special_regex: Option<Regex>,
+}
+
+impl BPETokenizer {
+    pub fn new(merges: Vec<Pair>, special_tokens: Vec<String>) -> Self {
+        let mut vocab: Vec<Vec<u8>> = (0..=u8::MAX).map(|b| vec![b]).collect();
+        for (left, right) in &merges {
+            let bytes = [vocab[*left].as_slice(), vocab[*right].as_slice()].concat();
+            vocab.push(bytes);
+        }
+
+        let special_regex = special_tokens_regex(&special_tokens);
+        let special_tokens = special_tokens
+            .into_iter()
+            .map(|token| {
+                vocab.push(token.as_bytes().to_vec());
+                (token, vocab.len() - 1)
+            })
+            .collect();
+
+        Self {
+            ranks: merges
+                .iter()
+                .enumerate()
+                .map(|(rank, pair)| (*pair, rank))
+                .collect(),
+            merges,
+            vocab,
+            special_tokens,
+            special_regex,
+        }
+    }
+
+    pub fn vocab_size(&self) -> usize {
+        self.vocab.len()
+    }
+
+    pub fn merges(&self) -> &[Pair] {
+        &self.merges
+    }
+
+    pub fn special_token_id(&self, token: &str) -> Option<usize> {
+        self.special_tokens.get(token).copied()
+    }
+
+    /// The special token with the given id, if it is one.
+    pub fn special_token(&self, id: usize) -> Option<&str> {
+        self.special_tokens
+            .iter()
+            .find(|(_, special_id)| **special_id == id)
+            .map(|(token, _)| token.as_str())
+    }
+
+    pub fn token_bytes(&self, id: usize) -> Option<&[u8]> {
+        self.vocab.get(id).map(Vec::as_slice)
+    }
+
+    fn encode_word(&self, word: &str) -> Vec<usize> {
+        let mut ids: Vec<usize> = word.bytes().map(usize::from).collect();
+
+        while let Some((rank, pair)) = ids
+            .windows(2)
+            .filter_map(|pair| {
+                let pair = (pair[0], pair[1]);
+                self.ranks.get(&pair).map(|rank| (*rank, pair))
+            })
+            .min()
+        {
+            ids = merge(&ids, pair, BYTE_TOKENS + rank);
+        }
+
+        ids
+    }
+}
+
+impl Tokenizer for BPETokenizer {
+    fn encode(&self, text: String) -> Result<Vec<usize>> {
+        let mut ids = Vec::new();
+
+        for segment in split_special(self.special_regex.as_ref(), &text) {
+            match segment {
+                Segment::Special(token) => ids.push(self.special_tokens[token]),
+                Segment::Text(text) => {
+                    for word in PRE_TOKENIZE_REGEX.find_iter(text) {
+                        ids.extend(self.encode_word(word.as_str()));
+                    }
+                }
+            }
+        }
+
+        Ok(ids)
+    }
+
+    fn decode(&self, ids: Vec<usize>) -> Result<String> {
+        let mut bytes = Vec::new();
+        for id in ids {
+            bytes.extend(self.token_bytes(id).ok_or(Error::UnknownId(id))?);
+        }
+
+        Ok(String::from_utf8(bytes)?)
+    }
+
+    fn decode_bytes(&self, id: usize) -> Result<Vec<u8>> {
+        self.token_bytes(id)
+            .map(<[u8]>::to_vec)
+            .ok_or(Error::UnknownId(id))
+    }
+
+    fn vocab_size(&self) -> usize {
+        BPETokenizer::vocab_size(self)
+    }
+
+    fn to_file(&self) -> TokenizerFile {
+        TokenizerFile::new(TokenizerModel::Bpe {
+            vocab: bpe_vocab(self),
+            merges: self.merges.clone(),
+            special_tokens: self
+                .special_tokens
+                .iter()
+                .map(|(token, id)| (token.clone(), *id))
+                .collect(),
+        })
+    }
+}
+
+/// Replaces every occurrence of `pair` in `ids` with `new_id`.
+fn merge(ids: &[usize], pair: Pair, new_id: usize) -> Vec<usize> {
+    let mut merged = Vec::with_capacity(ids.len());
+    let mut i = 0;
+
+    while i < ids.len() {
+        if i + 1 < ids.len() && (ids[i], ids[i + 1]) == pair {
+            merged.push(new_id);
+            i += 2;
+        } else {
+            merged.push(ids[i]);
+            i += 1;
+        }
+    }
+
+    merged
+}
+
+fn special_tokens_regex(special_tokens: &[String]) -> Option<Regex> {
+    if special_tokens.is_empty() {
+        return None;
+    }
+
+    let alternation = special_tokens
+        .iter()
+        .map(|token| regex::escape(token))
+        .collect::<Vec<_>>()
+        .join("|");
+
+    Some(Regex::new(&alternation).unwrap())
+}
+
+enum Segment<'a> {
+    Text(&'a str),
+    Special(&'a str),
+}
+
+/// Splits `text` around occurrences of special tokens.
+fn split_special<'a>(special_regex: Option<&Regex>, text: &'a str) -> Vec<Segment<'a>> {
+    let Some(regex) = special_regex else {
+        return vec![Segment::Text(text)];
+    };
+
+    let mut segments = Vec::new();
+    let mut last_end = 0;
+
+    for mat in regex.find_iter(text) {
+        if mat.start() > last_end {
+            segments.push(Segment::Text(&text[last_end..mat.start()]));
+        }
+        segments.push(Segment::Special(mat.as_str()));
+        last_end = mat.end();
+    }
+
+    if last_end < text.len() {
+        segments.push(Segment::Text(&text[last_end..]));
+    }
+
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error::Error,
+        listings::ch02::{
+            Tokenizer,
+            corpus::Corpus,
+            tokenizers::{BPETokenizer, BPETrainer, bpe::byte_to_char},
+        },
+    };
+
+    const CORPUS: &str = "low low low low low lower lower newest newest newest newest newest \
+                          newest widest widest widest";
+
+    fn train(vocab_size: usize) -> BPETokenizer {
+        BPETrainer::new(vocab_size)
+            .special_tokens(vec!["<|endoftext|>".to_string()])
+            .train(&Corpus::Raw(CORPUS.to_string()))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_bpe_trainer_merges() {
+        let tokenizer = train(256 + 3 + 1);
+
+        assert_eq!(tokenizer.vocab_size(), 260);
+        // "es" and "st" tie as the most frequent pair, then "lo" and "ow" tie once "est" is
+        // formed.
+        assert_eq!(
+            tokenizer.merges(),
+            [
+                (b'e' as usize, b's' as usize),
+                (256, b't' as usize),
+                (b'l' as usize, b'o' as usize)
+            ]
+        );
+        assert_eq!(tokenizer.special_token_id("<|endoftext|>"), Some(259));
+    }
+
+    #[test]
+    fn test_bpe_tokenizer_roundtrip() {
+        let tokenizer = train(300);
+
+        let input = "the newest <|endoftext|> lowest widget, ünïcödé";
+        let ids = tokenizer.encode(input.to_string()).unwrap();
+
+        assert!(ids.contains(&tokenizer.special_token_id("<|endoftext|>").unwrap()));
+        assert!(ids.len() < input.len());
+        assert_eq!(tokenizer.decode(ids).unwrap(), input);
+
+        assert_eq!(tokenizer.encode(" newest".to_string()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_bpe_tokenizer_decode_errors() {
+        let tokenizer = train(300);
+
+        assert!(matches!(
+            tokenizer.decode(vec![b'a' as usize, 300]),
+            Err(Error::UnknownId(300))
+        ));
+
+        // The first byte of "é" on its own.
+        assert!(matches!(
+            tokenizer.decode(vec![0xC3]),
+            Err(Error::InvalidUtf8(_))
+        ));
+    }
+
+    #[test]
+    fn test_bpe_tokenizer_offsets() {
+        let tokenizer = train(300);
+
+        let text = "the newest ünïcödé <|endoftext|> lowest";
+        let spans = tokenizer.encode_with_offsets(text).unwrap();
+
+        assert_eq!(
+            spans.iter().map(|span| span.id).collect::<Vec<_>>(),
+            tokenizer.encode(text.to_string()).unwrap()
+        );
+        assert_eq!(spans.first().unwrap().bytes.start, 0);
+        assert_eq!(spans.last().unwrap().bytes.end, text.len());
+        assert_eq!(spans.last().unwrap().chars.end, text.chars().count());
+        for pair in spans.windows(2) {
+            assert_eq!(pair[0].bytes.end, pair[1].bytes.start);
+        }
+
+        let special = spans
+            .iter()
+            .find(|span| Some(span.id) == tokenizer.special_token_id("<|endoftext|>"))
+            .unwrap();
+        assert_eq!(&text[special.bytes.clone()], "<|endoftext|>");
+        assert_eq!(special.chars, 19..32);
+
+        // "ü" is two bytes, and with no merges for it each byte is its own token over one char.
+        let u = spans.iter().position(|span| span.id == 0xC3).unwrap();
+        assert_eq!(spans[u].chars, spans[u + 1].chars);
+        assert_eq!(tokenizer.decode_bytes(spans[u].id).unwrap(), [0xC3]);
+    }
+
+    #[test]
+    fn test_byte_to_char() {
+        assert_eq!(byte_to_char(b'a'), 'a');
+        assert_eq!(byte_to_char(b' '), 'Ġ');
+        assert_eq!(byte_to_char(b'\n'), 'Ċ');
+        assert_eq!(byte_to_char(0xAD), 'Ń');
+    }
+
+    #[test]
+    fn test_bpe_trainer_vocab_too_small() {
+        assert!(matches!(
+            BPETrainer::new(256)
+                .special_tokens(vec!["<|endoftext|>".to_string()])
+                .train(&Corpus::Raw(CORPUS.to_string())),
+            Err(Error::InvalidTokenizer(_))
+        ));
+    }
+}
@@ -0,0 +1,507 @@
+use std::{borrow::Cow, collections::HashMap, fs::File, io::BufReader, path::Path, sync::LazyLock};
+
+use fancy_regex::Regex;
+use serde::Deserialize;
+
+use crate::{
+    error::{Error, Result},
+    listings::ch02::{
+        TokenSpan, Tokenizer,
+        tokenizers::{
+            bpe::byte_to_char,
+            serialization::{TokenizerFile, TokenizerModel},
+        },
+    },
+};
+
+/// The pre-tokenization pattern Hugging Face's `ByteLevel` pre-tokenizer uses when `use_regex` is
+/// set, which is the same one GPT-2 was trained with.
+const BYTE_LEVEL_PATTERN: &str =
+    r"'s|'t|'re|'ve|'m|'ll|'d| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+(?!\S)|\s+";
+
+static BYTE_LEVEL_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(BYTE_LEVEL_PATTERN).unwrap());
+
+static CHAR_TO_BYTE: LazyLock<HashMap<char, u8>> =
+    LazyLock::new(|| (0..=u8::MAX).map(|b| (byte_to_char(b), b)).collect());
+
+/// The subset of Hugging Face's `tokenizer.json` schema needed for byte-level BPE models.
+#[derive(Deserialize)]
+struct TokenizerJson {
+    #[serde(default)]
+    added_tokens: Vec<AddedToken>,
+    normalizer: Option<serde_json::Value>,
+    pre_tokenizer: Option<PreTokenizerJson>,
+    model: ModelJson,
+    decoder: Option<DecoderJson>,
+}
+
+#[derive(Deserialize)]
+struct AddedToken {
+    id: usize,
+    content: String,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum PreTokenizerJson {
+    ByteLevel {
+        #[serde(default)]
+        add_prefix_space: bool,
+        #[serde(default = "default_use_regex")]
+        use_regex: bool,
+    },
+    Split {
+        pattern: SplitPattern,
+        behavior: String,
+        #[serde(default)]
+        invert: bool,
+    },
+    Sequence {
+        pretokenizers: Vec<PreTokenizerJson>,
+    },
+}
+
+fn default_use_regex() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+enum SplitPattern {
+    Regex(String),
+    String(String),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum ModelJson {
+    #[serde(rename = "BPE")]
+    Bpe {
+        vocab: HashMap<String, usize>,
+        merges: Vec<MergeJson>,
+    },
+}
+
+/// Older files spell merges as `"a b"`, newer ones as `["a", "b"]`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MergeJson {
+    Joined(String),
+    Pair(String, String),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum DecoderJson {
+    ByteLevel {},
+}
+
+/// One step of the pre-tokenization pipeline.
+#[derive(Debug)]
+enum PreTokenizer {
+    /// Splits on every match of the pattern, keeping matches and the text between them.
+    Split(Regex),
+    /// Splits with [`BYTE_LEVEL_PATTERN`].
+    ByteLevel,
+}
+
+/// A byte-level BPE tokenizer read from a Hugging Face `tokenizer.json`.
+///
+/// Supports the files produced for GPT-2-style models: a `BPE` model, `ByteLevel` and regex
+/// `Split` pre-tokenizers (optionally in a `Sequence`), added tokens, and a `ByteLevel` decoder.
+/// Anything else is rejected when loading rather than silently encoded differently.
+#[derive(Debug)]
+pub struct HuggingFaceTokenizer {
+    source: serde_json::Value,
+    vocab: HashMap<String, usize>,
+    id_to_token: HashMap<usize, String>,
+    /// Maps a pair of ids to its merge rank and the id it merges into.
+    merges: HashMap<(usize, usize), (usize, usize)>,
+    byte_ids: [usize; 256],
+    added_tokens: HashMap<String, usize>,
+    added_regex: Option<regex::Regex>,
+    add_prefix_space: bool,
+    pre_tokenizers: Vec<PreTokenizer>,
+}
+
+impl HuggingFaceTokenizer {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let file = File::open(path).map_err(|source| Error::File {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let source: serde_json::Value = serde_json::from_reader(BufReader::new(file))?;
+
+        Self::from_value(source)
+    }
+
+    pub fn from_value(source: serde_json::Value) -> Result<Self> {
+        let json: TokenizerJson = serde_json::from_value(source.clone())?;
+
+        if json.normalizer.as_ref().is_some_and(|n| !n.is_null()) {
+            return Err(unsupported("tokenizer.json normalizers aren't supported"));
+        }
+
+        let mut add_prefix_space = false;
+        let mut byte_level = false;
+        let mut pre_tokenizers = Vec::new();
+        let mut pending: Vec<_> = json.pre_tokenizer.into_iter().collect();
+        while let Some(pre_tokenizer) = pending.pop() {
+            match pre_tokenizer {
+                PreTokenizerJson::Sequence { pretokenizers } => {
+                    pending.extend(pretokenizers.into_iter().rev())
+                }
+                PreTokenizerJson::ByteLevel {
+                    add_prefix_space: prefix,
+                    use_regex,
+                } => {
+                    byte_level = true;
+                    add_prefix_space |= prefix;
+                    if use_regex {
+                        pre_tokenizers.push(PreTokenizer::ByteLevel);
+                    }
+                }
+                PreTokenizerJson::Split {
+                    pattern,
+                    behavior,
+                    invert,
+                } => {
+                    if behavior != "Isolated" || invert {
+                        return Err(unsupported(format!(
+                            "only Isolated, non-inverted Split pre-tokenizers are supported, got \
+                             {behavior} (invert: {invert})"
+                        )));
+                    }
+                    let pattern = match pattern {
+                        SplitPattern::Regex(pattern) => pattern,
+                        SplitPattern::String(literal) => fancy_regex::escape(&literal).to_string(),
+                    };
+                    pre_tokenizers.push(PreTokenizer::Split(Regex::new(&pattern)?));
+                }
+            }
+        }
+
+        if !byte_level || !matches!(json.decoder, Some(DecoderJson::ByteLevel {})) {
+            return Err(unsupported("only byte-level BPE tokenizers are supported"));
+        }
+
+        let ModelJson::Bpe { vocab, merges } = json.model;
+
+        let mut byte_ids = [0; 256];
+        for b in 0..=u8::MAX {
+            byte_ids[b as usize] = *vocab
+                .get(&byte_to_char(b).to_string())
+                .ok_or_else(|| unsupported(format!("vocab is missing byte {b:#04x}")))?;
+        }
+
+        let merges = merges
+            .into_iter()
+            .enumerate()
+            .map(|(rank, merge)| {
+                let (left, right) = match merge {
+                    MergeJson::Pair(left, right) => (left, right),
+                    MergeJson::Joined(joined) => match joined.split_once(' ') {
+                        Some((left, right)) => (left.to_string(), right.to_string()),
+                        None => return Err(unsupported(format!("malformed merge {joined:?}"))),
+                    },
+                };
+
+                let id = |token: &str| {
+                    vocab.get(token).copied().ok_or_else(|| {
+                        unsupported(format!("merge {rank} uses unknown token {token:?}"))
+                    })
+                };
+
+                Ok(((id(&left)?, id(&right)?), (rank, id(&(left + &right))?)))
+            })
+            .collect::<Result<_>>()?;
+
+        let mut id_to_token: HashMap<usize, String> = vocab
+            .iter()
+            .map(|(token, id)| (*id, token.clone()))
+            .collect();
+        let added_tokens: HashMap<String, usize> = json
+            .added_tokens
+            .into_iter()
+            .map(|token| (token.content, token.id))
+            .collect();
+        for (content, id) in &added_tokens {
+            id_to_token.insert(*id, content.clone());
+        }
+
+        let added_regex = if added_tokens.is_empty() {
+            None
+        } else {
+            let mut contents: Vec<_> = added_tokens.keys().collect();
+            // NB: Longest first, so that a token that's a prefix of another doesn't shadow it.
+            contents.sort_by_key(|content| std::cmp::Reverse(content.len()));
+            let alternation = contents
+                .into_iter()
+                .map(|content| regex::escape(content))
+                .collect::<Vec<_>>()
+                .join("|");
+            Some(regex::Regex::new(&alternation)?)
+        };
+
+        Ok(Self {
+            source,
+            vocab,
+            id_to_token,
+            merges,
+            byte_ids,
+            added_tokens,
+            added_regex,
+            add_prefix_space,
+            pre_tokenizers,
+        })
+    }
+
+    pub fn token_to_id(&self, token: &str) -> Option<usize> {
+        self.added_tokens
+            .get(token)
+            .or_else(|| self.vocab.get(token))
+            .copied()
+    }
+
+    fn pre_tokenize<'a>(&self, text: &'a str) -> Vec<&'a str> {
+        let mut pieces = vec![text];
+
+        for pre_tokenizer in &self.pre_tokenizers {
+            let regex = match pre_tokenizer {
+                PreTokenizer::Split(regex) => regex,
+                PreTokenizer::ByteLevel => &*BYTE_LEVEL_REGEX,
+            };
+
+            pieces = pieces
+                .into_iter()
+                .flat_map(|piece| split_isolated(regex, piece))
+                .collect();
+        }
+
+        pieces
+    }
+
+    fn encode_piece(&self, piece: &str) -> Vec<usize> {
+        let mut ids: Vec<usize> = piece.bytes().map(|b| self.byte_ids[b as usize]).collect();
+
+        while let Some((_, i, merged)) = ids
+            .windows(2)
+            .enumerate()
+            .filter_map(|(i, pair)| {
+                self.merges
+                    .get(&(pair[0], pair[1]))
+                    .map(|(rank, merged)| (*rank, i, *merged))
+            })
+            .min()
+        {
+            ids.splice(i..i + 2, [merged]);
+        }
+
+        ids
+    }
+}
+
+fn unsupported(reason: impl Into<String>) -> Error {
+    Error::InvalidTokenizer(reason.into())
+}
+
+/// Splits `text` at every match of `regex`, keeping both the matches and the text between them.
+fn split_isolated<'a>(regex: &Regex, text: &'a str) -> Vec<&'a str> {
+    let mut pieces = Vec::new();
+    let mut last_end = 0;
+
+    for mat in regex.find_iter(text).flatten() {
+        if mat.start() > last_end {
+            pieces.push(&text[last_end..mat.start()]);
+        }
+        if !mat.as_str().is_empty() {
+            pieces.push(mat.as_str());
+        }
+        last_end = mat.end();
+    }
+
+    if last_end < text.len() {
+        pieces.push(&text[last_end..]);
+    }
+
+    pieces
+}
+
+impl Tokenizer for HuggingFaceTokenizer {
+    fn encode(&self, text: String) -> Result<Vec<usize>> {
+        let text: Cow<str> = if self.add_prefix_space && !text.starts_with(' ') {
+            format!(" {text}").into()
+        } else {
+            text.into()
+        };
+
+        let mut ids = Vec::new();
+        let mut last_end = 0;
+        let encode_ordinary = |ordinary: &str, ids: &mut Vec<usize>| {
+            for piece in self.pre_tokenize(ordinary) {
+                ids.extend(self.encode_piece(piece));
+            }
+        };
+
+        if let Some(added_regex) = &self.added_regex {
+            for mat in added_regex.find_iter(&text) {
+                encode_ordinary(&text[last_end..mat.start()], &mut ids);
+                ids.push(self.added_tokens[mat.as_str()]);
+                last_end = mat.end();
+            }
+        }
+        encode_ordinary(&text[last_end..], &mut ids);
+
+        Ok(ids)
+    }
+
+    fn encode_with_offsets(&self, text: &str) -> Result<Vec<TokenSpan>> {
+        let tokens = self
+            .encode(text.to_string())?
+            .into_iter()
+            .map(|id| Ok((id, self.decode_bytes(id)?.len())))
+            .collect::<Result<Vec<_>>>()?;
+        let skip = usize::from(self.add_prefix_space && !text.starts_with(' '));
+
+        Ok(TokenSpan::from_byte_lengths(text, tokens, skip))
+    }
+
+    fn decode(&self, ids: Vec<usize>) -> Result<String> {
+        let mut bytes = Vec::new();
+        for id in ids {
+            bytes.extend(self.decode_bytes(id)?);
+        }
+
+        Ok(String::from_utf8(bytes)?)
+    }
+
+    fn decode_bytes(&self, id: usize) -> Result<Vec<u8>> {
+        let token = self.id_to_token.get(&id).ok_or(Error::UnknownId(id))?;
+        if self.added_tokens.contains_key(token) {
+            return Ok(token.as_bytes().to_vec());
+        }
+
+        token
+            .chars()
+            .map(|c| {
+                CHAR_TO_BYTE.get(&c).copied().ok_or_else(|| {
+                    unsupported(format!(
+                        "token {token:?} isn't spelled in byte-level characters"
+                    ))
+                })
+            })
+            .collect()
+    }
+
+    fn vocab_size(&self) -> usize {
+        self.id_to_token.keys().max().map_or(0, |id| id + 1)
+    }
+
+    fn to_file(&self) -> TokenizerFile {
+        TokenizerFile::new(TokenizerModel::HuggingFace {
+            tokenizer: self.source.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use tiktoken_rs::CoreBPE;
+
+    use crate::{
+        error::Error,
+        listings::ch02::{Tokenizer, tokenizers::HuggingFaceTokenizer},
+    };
+
+    /// GPT-2's `tokenizer.json`, cut down to the byte tokens and its first 2000 merges.
+    fn fixture() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("data/fixtures/gpt2-2k-tokenizer.json")
+    }
+
+    /// tiktoken's GPT-2 encoding restricted to the same tokens as [`fixture`].
+    fn reference() -> CoreBPE {
+        let gpt2 = tiktoken_rs::r50k_base().unwrap();
+
+        CoreBPE::new(
+            (0..2256)
+                .map(|rank| {
+                    (
+                        gpt2._decode_native_and_split(vec![rank]).next().unwrap(),
+                        rank,
+                    )
+                })
+                .collect(),
+            [("<|endoftext|>".to_string(), 50256)].into_iter().collect(),
+            r"'s|'t|'re|'ve|'m|'ll|'d| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+(?!\S)|\s+",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_hf_tokenizer_matches_reference() {
+        let tokenizer = HuggingFaceTokenizer::from_file(&fixture()).unwrap();
+        let reference = reference();
+
+        for input in [
+            "Hello, do you like tea? <|endoftext|> In the sunlit terraces of someunknownPlace.",
+            "I HAD always thought Jack Gisburn rather a cheap genius--though a good fellow enough",
+            "  leading spaces, trailing spaces   \n\n\tand tabs; numbers 12345 & ünïcödé 🦀",
+        ] {
+            let expected: Vec<usize> = reference
+                .encode_with_special_tokens(input)
+                .into_iter()
+                .map(|id| id as usize)
+                .collect();
+
+            let ids = tokenizer.encode(input.to_string()).unwrap();
+            assert_eq!(ids, expected, "{input:?}");
+            assert_eq!(tokenizer.decode(ids).unwrap(), input);
+        }
+
+        assert!(matches!(
+            tokenizer.decode(vec![40000]),
+            Err(Error::UnknownId(40000))
+        ));
+
+        assert_eq!(tokenizer.token_to_id("<|endoftext|>"), Some(50256));
+        assert_eq!(tokenizer.token_to_id("Ġthe"), Some(262));
+    }
+
+    #[test]
+    fn test_hf_tokenizer_merges_as_pairs() {
+        let mut source: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(fixture()).unwrap()).unwrap();
+        let merges = source["model"]["merges"].as_array_mut().unwrap();
+        for merge in merges.iter_mut() {
+            let (left, right) = merge.as_str().unwrap().split_once(' ').unwrap();
+            *merge = serde_json::json!([left, right]);
+        }
+
+        let tokenizer = HuggingFaceTokenizer::from_value(source).unwrap();
+        let input = "In the sunlit terraces of the palace.";
+        assert_eq!(
+            tokenizer.encode(input.to_string()).unwrap(),
+            HuggingFaceTokenizer::from_file(&fixture())
+                .unwrap()
+                .encode(input.to_string())
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_hf_tokenizer_rejects_unsupported() {
+        let mut source: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(fixture()).unwrap()).unwrap();
+        source["normalizer"] = serde_json::json!({"type": "NFC"});
+        assert!(matches!(
+            HuggingFaceTokenizer::from_value(source.clone()),
+            Err(Error::InvalidTokenizer(_))
+        ));
+
+        source["normalizer"] = serde_json::Value::Null;
+        source["model"]["type"] = "WordPiece".into();
+        assert!(HuggingFaceTokenizer::from_value(source).is_err());
+    }
+}
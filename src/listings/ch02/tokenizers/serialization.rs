@@ -0,0 +1,296 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    listings::ch02::{
+        SimpleTokenizerV1, SimpleTokenizerV2, Tokenizer,
+        tokenizers::{
+            BPETokenizer, Encoding, HuggingFaceTokenizer, SpecialTokenPolicy, TiktokenTokenizer,
+            bpe::{BYTE_TOKENS, byte_to_char},
+        },
+    },
+};
+
+/// Bumped whenever [`TokenizerFile`] changes in a way older readers can't handle.
+pub const TOKENIZER_FILE_VERSION: u32 = 1;
+
+/// The on-disk form of every [`Tokenizer`] in this crate.
+///
+/// Vocabularies are written as token-to-id maps. Byte-level BPE tokens are spelled with GPT-2's
+/// printable byte alphabet (e.g. `Ġ` for a space), as in Hugging Face's `vocab.json`. Loading a
+/// file back reproduces the exact ids of the tokenizer it was saved from.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TokenizerFile {
+    pub version: u32,
+    #[serde(flatten)]
+    pub model: TokenizerModel,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TokenizerModel {
+    SimpleV1 {
+        vocab: BTreeMap<String, usize>,
+    },
+    SimpleV2 {
+        vocab: BTreeMap<String, usize>,
+        special_tokens: BTreeMap<String, usize>,
+    },
+    Bpe {
+        vocab: BTreeMap<String, usize>,
+        /// Pairs of ids, in rank order.
+        merges: Vec<(usize, usize)>,
+        special_tokens: BTreeMap<String, usize>,
+    },
+    /// A pretrained tiktoken encoding, which is identified by name rather than stored.
+    Tiktoken {
+        encoding: Encoding,
+        special_tokens: SpecialTokenPolicy,
+    },
+    /// A Hugging Face `tokenizer.json`, embedded verbatim.
+    HuggingFace {
+        tokenizer: serde_json::Value,
+    },
+}
+
+impl TokenizerFile {
+    pub fn new(model: TokenizerModel) -> Self {
+        Self {
+            version: TOKENIZER_FILE_VERSION,
+            model,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = File::create(path).map_err(|source| Error::File {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).map_err(|source| Error::File {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let file: Self = serde_json::from_reader(BufReader::new(file))?;
+
+        if file.version != TOKENIZER_FILE_VERSION {
+            return Err(Error::InvalidTokenizer(format!(
+                "unsupported tokenizer file version {} in {}, expected {TOKENIZER_FILE_VERSION}",
+                file.version,
+                path.display()
+            )));
+        }
+
+        Ok(file)
+    }
+
+    pub fn into_tokenizer(self) -> Result<Box<dyn Tokenizer>> {
+        Ok(match self.model {
+            TokenizerModel::SimpleV1 { vocab } => {
+                Box::new(SimpleTokenizerV1::new(vocab.into_iter().collect()))
+            }
+            TokenizerModel::SimpleV2 {
+                vocab,
+                special_tokens,
+            } => {
+                let vocab: HashMap<_, _> = vocab.into_iter().collect();
+                for (token, id) in special_tokens {
+                    if vocab.get(&token) != Some(&id) {
+                        return Err(Error::InvalidTokenizer(format!(
+                            "special token {token:?} is not id {id} in the vocab"
+                        )));
+                    }
+                }
+                Box::new(SimpleTokenizerV2::from_vocab(vocab)?)
+            }
+            TokenizerModel::Bpe {
+                vocab,
+                merges,
+                special_tokens,
+            } => {
+                for (rank, (left, right)) in merges.iter().enumerate() {
+                    if *left >= BYTE_TOKENS + rank || *right >= BYTE_TOKENS + rank {
+                        return Err(Error::InvalidTokenizer(format!(
+                            "BPE merge {rank} ({left}, {right}) refers to a later token"
+                        )));
+                    }
+                }
+
+                let mut special_tokens: Vec<_> = special_tokens.into_iter().collect();
+                special_tokens.sort_by_key(|(_, id)| *id);
+
+                let tokenizer = BPETokenizer::new(
+                    merges,
+                    special_tokens.into_iter().map(|(token, _)| token).collect(),
+                );
+                if bpe_vocab(&tokenizer) != vocab {
+                    return Err(Error::InvalidTokenizer(
+                        "BPE vocab doesn't match the one implied by its merges".to_string(),
+                    ));
+                }
+                Box::new(tokenizer)
+            }
+            TokenizerModel::Tiktoken {
+                encoding,
+                special_tokens,
+            } => Box::new(TiktokenTokenizer::new(encoding, special_tokens)?),
+            TokenizerModel::HuggingFace { tokenizer } => {
+                Box::new(HuggingFaceTokenizer::from_value(tokenizer)?)
+            }
+        })
+    }
+}
+
+/// Reads a tokenizer previously written by [`Tokenizer::save`].
+pub fn load_tokenizer(path: &Path) -> Result<Box<dyn Tokenizer>> {
+    TokenizerFile::load(path)?.into_tokenizer()
+}
+
+/// `tokenizer`'s vocabulary with each token spelled in GPT-2's printable byte alphabet.
+pub(crate) fn bpe_vocab(tokenizer: &BPETokenizer) -> BTreeMap<String, usize> {
+    (0..tokenizer.vocab_size())
+        .map(|id| {
+            let token = match tokenizer.special_token(id) {
+                Some(special) => special.to_string(),
+                None => tokenizer
+                    .token_bytes(id)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|b| byte_to_char(*b))
+                    .collect(),
+            };
+            (token, id)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::Path};
+
+    use tempfile::TempDir;
+
+    use crate::{
+        error::Error,
+        listings::ch02::{
+            SimpleTokenizerV1, SimpleTokenizerV2, Tokenizer,
+            corpus::Corpus,
+            tokenizers::{
+                BPETrainer, Encoding, HuggingFaceTokenizer, SpecialTokenPolicy, TiktokenTokenizer,
+                serialization::{TokenizerFile, TokenizerModel, load_tokenizer},
+            },
+        },
+    };
+
+    const TEXT: &str = "the cat sat on the mat. the dog sat on the log, newest of all!";
+
+    fn assert_roundtrips(tokenizer: &dyn Tokenizer, input: &str) {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("tokenizer.json");
+
+        tokenizer.save(&path).unwrap();
+        let loaded = load_tokenizer(&path).unwrap();
+
+        let ids = tokenizer.encode(input.to_string()).unwrap();
+        assert_eq!(loaded.encode(input.to_string()).unwrap(), ids);
+        assert_eq!(
+            loaded.decode(ids.clone()).unwrap(),
+            tokenizer.decode(ids).unwrap()
+        );
+        assert_eq!(loaded.to_file(), tokenizer.to_file());
+    }
+
+    #[test]
+    fn test_save_load_simple_tokenizers() {
+        let vocab = Corpus::Raw(TEXT.to_string()).to_vocab(None).unwrap();
+        assert_roundtrips(&SimpleTokenizerV1::new(vocab), "the dog sat on the cat.");
+
+        let tokenizer = SimpleTokenizerV2::new(Corpus::Raw(TEXT.to_string())).unwrap();
+        assert_roundtrips(&tokenizer, "the bird sat. <|endoftext|> the cat");
+
+        let TokenizerModel::SimpleV2 { special_tokens, .. } = tokenizer.to_file().model else {
+            panic!("expected a SimpleV2 model");
+        };
+        assert_eq!(
+            special_tokens.into_iter().collect::<HashMap<_, _>>(),
+            HashMap::from([
+                (
+                    "<|endoftext|>".to_string(),
+                    tokenizer.str_to_int["<|endoftext|>"]
+                ),
+                ("<|unk|>".to_string(), tokenizer.str_to_int["<|unk|>"]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_save_load_bpe_tokenizers() {
+        let tokenizer = BPETrainer::new(280)
+            .special_tokens(vec!["<|endoftext|>".to_string()])
+            .train(&Corpus::Raw(TEXT.to_string()))
+            .unwrap();
+        assert_roundtrips(&tokenizer, "the newest dog <|endoftext|> sat on a mat");
+
+        let TokenizerModel::Bpe { vocab, .. } = tokenizer.to_file().model else {
+            panic!("expected a BPE model");
+        };
+        assert_eq!(vocab["Ġ"], b' ' as usize);
+        assert_eq!(vocab["<|endoftext|>"], 279);
+
+        let tokenizer = TiktokenTokenizer::new(
+            Encoding::Cl100k,
+            SpecialTokenPolicy::allow(["<|endoftext|>"]),
+        )
+        .unwrap();
+        assert_roundtrips(&tokenizer, "the newest dog <|endoftext|> sat on a mat");
+
+        let fixture =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("data/fixtures/gpt2-2k-tokenizer.json");
+        assert_roundtrips(&HuggingFaceTokenizer::from_file(&fixture).unwrap(), TEXT);
+    }
+
+    #[test]
+    fn test_load_rejects_inconsistent_files() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("tokenizer.json");
+
+        let mut file = BPETrainer::new(260)
+            .train(&Corpus::Raw(TEXT.to_string()))
+            .unwrap()
+            .to_file();
+        let TokenizerModel::Bpe { merges, .. } = &mut file.model else {
+            panic!("expected a BPE model");
+        };
+        merges.swap(0, 1);
+        file.save(&path).unwrap();
+        assert!(matches!(
+            load_tokenizer(&path),
+            Err(Error::InvalidTokenizer(_))
+        ));
+
+        let mut file = TokenizerFile::new(TokenizerModel::Tiktoken {
+            encoding: Encoding::Gpt2,
+            special_tokens: SpecialTokenPolicy::Disallow,
+        });
+        file.version += 1;
+        file.save(&path).unwrap();
+        assert!(matches!(
+            load_tokenizer(&path),
+            Err(Error::InvalidTokenizer(_))
+        ));
+    }
+}
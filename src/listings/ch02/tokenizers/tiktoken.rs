@@ -0,0 +1,357 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt,
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+use tiktoken_rs::CoreBPE;
+
+use crate::{
+    error::{Error, Result},
+    listings::ch02::{
+        Tokenizer,
+        tokenizers::serialization::{TokenizerFile, TokenizerModel},
+    },
+};
+
+/// The pretrained encodings shipped with tiktoken.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Encoding {
+    /// GPT-2's encoding, which tiktoken serves as `r50k_base`.
+    #[serde(rename = "gpt2")]
+    Gpt2,
+    #[serde(rename = "r50k_base")]
+    R50k,
+    #[serde(rename = "p50k_base")]
+    P50k,
+    #[serde(rename = "cl100k_base")]
+    Cl100k,
+    #[serde(rename = "o200k_base")]
+    O200k,
+}
+
+impl Encoding {
+    fn load(self) -> Result<CoreBPE> {
+        match self {
+            Self::Gpt2 | Self::R50k => tiktoken_rs::r50k_base(),
+            Self::P50k => tiktoken_rs::p50k_base(),
+            Self::Cl100k => tiktoken_rs::cl100k_base(),
+            Self::O200k => tiktoken_rs::o200k_base(),
+        }
+        .map_err(|e| Error::Tiktoken(e.to_string()))
+    }
+
+    /// NB: tiktoken-rs doesn't expose this, so these are the `n_vocab`s Python's tiktoken reports
+    /// for the same ranks and special tokens.
+    fn vocab_size(self) -> usize {
+        match self {
+            Self::Gpt2 | Self::R50k => 50257,
+            Self::P50k => 50281,
+            Self::Cl100k => 100277,
+            Self::O200k => 200019,
+        }
+    }
+
+    /// Ordinary tokens are ranked from 0 up to this, and special tokens, apart from p50k's
+    /// `<|endoftext|>`, come after it.
+    fn ordinary_ranks(self) -> usize {
+        match self {
+            Self::Gpt2 | Self::R50k => 50256,
+            Self::P50k => 50281,
+            Self::Cl100k => 100256,
+            Self::O200k => 199998,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Gpt2 => "gpt2",
+            Self::R50k => "r50k_base",
+            Self::P50k => "p50k_base",
+            Self::Cl100k => "cl100k_base",
+            Self::O200k => "o200k_base",
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        [
+            Self::Gpt2,
+            Self::R50k,
+            Self::P50k,
+            Self::Cl100k,
+            Self::O200k,
+        ]
+        .into_iter()
+        .find(|encoding| encoding.name() == s)
+        .ok_or_else(|| Error::Tiktoken(format!("unknown encoding {s:?}")))
+    }
+}
+
+/// What to do with an encoding's special tokens, such as `<|endoftext|>`, when they appear in the
+/// text being encoded.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpecialTokenPolicy {
+    /// Encode the listed special tokens as their ids. Any other special token is an error.
+    Allow(BTreeSet<String>),
+    /// Fail with [`Error::DisallowedSpecialToken`] if any special token appears, as tiktoken does
+    /// by default.
+    #[default]
+    Disallow,
+    /// Encode special tokens as if they were ordinary text.
+    Text,
+}
+
+impl SpecialTokenPolicy {
+    pub fn allow<I, S>(tokens: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self::Allow(tokens.into_iter().map(Into::into).collect())
+    }
+}
+
+/// A pretrained tiktoken encoding with an explicit [`SpecialTokenPolicy`].
+pub struct TiktokenTokenizer {
+    encoding: Encoding,
+    special_tokens: SpecialTokenPolicy,
+    bpe: CoreBPE,
+    special_ids: HashSet<usize>,
+}
+
+impl TiktokenTokenizer {
+    /// Fails with [`Error::UnknownToken`] if `special_tokens` allows a token that isn't one of
+    /// `encoding`'s special tokens.
+    pub fn new(encoding: Encoding, special_tokens: SpecialTokenPolicy) -> Result<Self> {
+        let bpe = encoding.load()?;
+
+        if let SpecialTokenPolicy::Allow(allowed) = &special_tokens {
+            let known = bpe.special_tokens();
+            if let Some(token) = allowed.iter().find(|token| !known.contains(token.as_str())) {
+                return Err(Error::UnknownToken(token.clone()));
+            }
+        }
+
+        let special_ids = bpe
+            .special_tokens()
+            .into_iter()
+            .flat_map(|token| bpe.encode_with_special_tokens(token))
+            .map(|id| id as usize)
+            .collect();
+
+        Ok(Self {
+            encoding,
+            special_tokens,
+            bpe,
+            special_ids,
+        })
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn special_tokens(&self) -> &SpecialTokenPolicy {
+        &self.special_tokens
+    }
+
+    fn decode_all(&self, ids: Vec<usize>) -> Result<Vec<u8>> {
+        // NB: tiktoken-rs panics on unknown ids when decoding to bytes, so they're caught first.
+        if let Some(id) = ids
+            .iter()
+            .find(|id| **id >= self.encoding.ordinary_ranks() && !self.special_ids.contains(id))
+        {
+            return Err(Error::UnknownId(*id));
+        }
+
+        let tokens = ids.into_iter().map(|id| id as u32).collect();
+        Ok(self
+            .bpe
+            ._decode_native_and_split(tokens)
+            .flatten()
+            .collect())
+    }
+}
+
+impl Tokenizer for TiktokenTokenizer {
+    fn encode(&self, text: String) -> Result<Vec<usize>> {
+        let allowed: HashSet<&str> = match &self.special_tokens {
+            SpecialTokenPolicy::Allow(allowed) => allowed.iter().map(String::as_str).collect(),
+            SpecialTokenPolicy::Disallow => HashSet::new(),
+            SpecialTokenPolicy::Text => {
+                let ids = self.bpe.encode_ordinary(&text);
+                return Ok(ids.into_iter().map(|id| id as usize).collect());
+            }
+        };
+
+        if let Some(token) = self
+            .bpe
+            .special_tokens()
+            .into_iter()
+            .find(|token| !allowed.contains(token) && text.contains(token))
+        {
+            return Err(Error::DisallowedSpecialToken(token.to_string()));
+        }
+
+        let (ids, _) = self.bpe.encode(&text, &allowed);
+        Ok(ids.into_iter().map(|id| id as usize).collect())
+    }
+
+    fn decode(&self, ids: Vec<usize>) -> Result<String> {
+        Ok(String::from_utf8(self.decode_all(ids)?)?)
+    }
+
+    fn decode_bytes(&self, id: usize) -> Result<Vec<u8>> {
+        self.decode_all(vec![id])
+    }
+
+    fn vocab_size(&self) -> usize {
+        self.encoding.vocab_size()
+    }
+
+    fn to_file(&self) -> TokenizerFile {
+        TokenizerFile::new(TokenizerModel::Tiktoken {
+            encoding: self.encoding,
+            special_tokens: self.special_tokens.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use crate::{
+        error::Error,
+        listings::ch02::{
+            Tokenizer,
+            tokenizers::{Encoding, SpecialTokenPolicy, TiktokenTokenizer},
+        },
+    };
+
+    const TEXT: &str =
+        "Hello, do you like tea? <|endoftext|> In the sunlit terraces of someunknownPlace.";
+
+    #[test]
+    fn test_allowed_special_tokens() {
+        let tokenizer =
+            TiktokenTokenizer::new(Encoding::Gpt2, SpecialTokenPolicy::allow(["<|endoftext|>"]))
+                .unwrap();
+
+        let ids = tokenizer.encode(TEXT.to_string()).unwrap();
+        assert_eq!(
+            ids,
+            [
+                15496, 11, 466, 345, 588, 8887, 30, 220, 50256, 554, 262, 4252, 18250, 8812, 2114,
+                286, 617, 34680, 27271, 13
+            ],
+        );
+        assert_eq!(tokenizer.decode(ids).unwrap(), TEXT);
+    }
+
+    #[test]
+    fn test_disallowed_special_tokens() {
+        let tokenizer =
+            TiktokenTokenizer::new(Encoding::Gpt2, SpecialTokenPolicy::Disallow).unwrap();
+
+        assert!(matches!(
+            tokenizer.encode(TEXT.to_string()),
+            Err(Error::DisallowedSpecialToken(token)) if token == "<|endoftext|>"
+        ));
+        assert_eq!(
+            tokenizer.encode("Hello, world.".to_string()).unwrap(),
+            [15496, 11, 995, 13]
+        );
+
+        let tokenizer = TiktokenTokenizer::new(
+            Encoding::Cl100k,
+            SpecialTokenPolicy::allow(["<|endoftext|>"]),
+        )
+        .unwrap();
+        assert!(matches!(
+            tokenizer.encode("<|endoftext|><|fim_prefix|>".to_string()),
+            Err(Error::DisallowedSpecialToken(token)) if token == "<|fim_prefix|>"
+        ));
+    }
+
+    #[test]
+    fn test_special_tokens_as_text() {
+        let tokenizer = TiktokenTokenizer::new(Encoding::Gpt2, SpecialTokenPolicy::Text).unwrap();
+
+        let ids = tokenizer.encode(TEXT.to_string()).unwrap();
+        assert!(!ids.contains(&50256));
+        assert_eq!(tokenizer.decode(ids).unwrap(), TEXT);
+    }
+
+    #[rstest]
+    #[case(Encoding::Gpt2, 50256, 50257)]
+    #[case(Encoding::R50k, 50256, 50257)]
+    #[case(Encoding::P50k, 50256, 50281)]
+    #[case(Encoding::Cl100k, 100257, 100261)]
+    #[case(Encoding::O200k, 199999, 200000)]
+    fn test_encodings(
+        #[case] encoding: Encoding,
+        #[case] end_of_text: usize,
+        #[case] known_ids: usize,
+    ) {
+        let tokenizer =
+            TiktokenTokenizer::new(encoding, SpecialTokenPolicy::allow(["<|endoftext|>"])).unwrap();
+
+        let ids = tokenizer.encode(TEXT.to_string()).unwrap();
+        assert!(ids.contains(&end_of_text));
+        assert_eq!(tokenizer.decode(ids).unwrap(), TEXT);
+        assert_eq!(encoding.to_string().parse::<Encoding>().unwrap(), encoding);
+
+        // Every id either decodes or is unknown, including those in the gaps between the
+        // ordinary and special tokens.
+        let vocab_size = tokenizer.vocab_size();
+        tokenizer.decode_bytes(vocab_size - 1).unwrap();
+        let decoded = (0..vocab_size + 1)
+            .filter(|id| match tokenizer.decode_bytes(*id) {
+                Ok(_) => true,
+                Err(Error::UnknownId(unknown)) => unknown != *id,
+                Err(_) => true,
+            })
+            .count();
+        assert_eq!(decoded, known_ids);
+    }
+
+    #[test]
+    fn test_tiktoken_errors() {
+        assert!(matches!(
+            TiktokenTokenizer::new(Encoding::Gpt2, SpecialTokenPolicy::allow(["<|unk|>"])),
+            Err(Error::UnknownToken(_))
+        ));
+
+        let tokenizer = TiktokenTokenizer::new(Encoding::Gpt2, SpecialTokenPolicy::Text).unwrap();
+        assert!(matches!(
+            tokenizer.decode(vec![15496, 60000]),
+            Err(Error::UnknownId(60000))
+        ));
+        // The first of the byte-level tokens a multi-byte character is split into.
+        let ids = tokenizer.encode("🦀".to_string()).unwrap();
+        assert!(ids.len() > 1);
+        assert_eq!(
+            ids.iter()
+                .flat_map(|id| tokenizer.decode_bytes(*id).unwrap())
+                .collect::<Vec<_>>(),
+            "🦀".as_bytes()
+        );
+        assert!(matches!(
+            tokenizer.decode(ids[..1].to_vec()),
+            Err(Error::InvalidUtf8(_))
+        ));
+    }
+}
@@ -1,12 +1,793 @@
+pub mod kv_cache;
+pub mod rope;
+
+use std::ops::Range;
+
+use burn::{
+    config::Config,
+    module::{Module, Param},
+    nn::{Dropout, DropoutConfig, Initializer, Linear, LinearConfig},
+    tensor::{Bool, Int, Tensor, TensorData, activation::softmax, backend::Backend, s},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    listings::ch03::{
+        kv_cache::KVCache,
+        rope::{Rope, RopeConfig},
+    },
+};
+
+/// `queries · keysᵀ / √d_k`, over the last two dimensions.
+fn attention_scores<B: Backend, const D: usize>(
+    queries: Tensor<B, D>,
+    keys: Tensor<B, D>,
+) -> Tensor<B, D> {
+    let d_k = keys.dims()[D - 1];
+    queries.matmul(keys.transpose()) / (d_k as f64).sqrt()
+}
+
+/// `true` above the diagonal, i.e. where a key comes after its query.
+fn causal_mask<B: Backend>(context_length: usize, device: &B::Device) -> Tensor<B, 2, Bool> {
+    Tensor::tril_mask([context_length, context_length], 0, device)
+}
+
+#[derive(Config, Debug)]
+pub struct SelfAttentionV1Config {
+    pub d_in: usize,
+    pub d_out: usize,
+}
+
+impl SelfAttentionV1Config {
+    /// Draws the weights uniformly from `[0, 1)`, like the book's `torch.rand`.
+    pub fn init<B: Backend>(&self, device: &B::Device) -> SelfAttentionV1<B> {
+        let initializer = Initializer::Uniform { min: 0.0, max: 1.0 };
+        let shape = [self.d_in, self.d_out];
+
+        SelfAttentionV1 {
+            w_query: initializer.init(shape, device),
+            w_key: initializer.init(shape, device),
+            w_value: initializer.init(shape, device),
+        }
+    }
+}
+
+/// The book's first self-attention module, with its weights as raw `[d_in, d_out]` parameters.
+#[derive(Module, Debug)]
+pub struct SelfAttentionV1<B: Backend> {
+    pub w_query: Param<Tensor<B, 2>>,
+    pub w_key: Param<Tensor<B, 2>>,
+    pub w_value: Param<Tensor<B, 2>>,
+}
+
+impl<B: Backend> SelfAttentionV1<B> {
+    /// Maps `[tokens, d_in]` inputs to `[tokens, d_out]` context vectors.
+    pub fn forward(&self, x: Tensor<B, 2>) -> Tensor<B, 2> {
+        let queries = x.clone().matmul(self.w_query.val());
+        let keys = x.clone().matmul(self.w_key.val());
+        let values = x.matmul(self.w_value.val());
+
+        softmax(attention_scores(queries, keys), 1).matmul(values)
+    }
+}
+
+#[derive(Config, Debug)]
+pub struct SelfAttentionV2Config {
+    pub d_in: usize,
+    pub d_out: usize,
+    #[config(default = false)]
+    pub qkv_bias: bool,
+}
+
+impl SelfAttentionV2Config {
+    pub fn init<B: Backend>(&self, device: &B::Device) -> SelfAttentionV2<B> {
+        let linear = LinearConfig::new(self.d_in, self.d_out).with_bias(self.qkv_bias);
+
+        SelfAttentionV2 {
+            w_query: linear.init(device),
+            w_key: linear.init(device),
+            w_value: linear.init(device),
+        }
+    }
+}
+
+/// [`SelfAttentionV1`] with [`Linear`] layers in place of the raw weights.
+#[derive(Module, Debug)]
+pub struct SelfAttentionV2<B: Backend> {
+    pub w_query: Linear<B>,
+    pub w_key: Linear<B>,
+    pub w_value: Linear<B>,
+}
+
+impl<B: Backend> SelfAttentionV2<B> {
+    /// Maps `[tokens, d_in]` inputs to `[tokens, d_out]` context vectors.
+    pub fn forward(&self, x: Tensor<B, 2>) -> Tensor<B, 2> {
+        let queries = self.w_query.forward(x.clone());
+        let keys = self.w_key.forward(x.clone());
+        let values = self.w_value.forward(x);
+
+        softmax(attention_scores(queries, keys), 1).matmul(values)
+    }
+}
+
+#[derive(Config, Debug)]
+pub struct CausalAttentionConfig {
+    pub d_in: usize,
+    pub d_out: usize,
+    /// The most tokens a batch can have.
+    pub context_length: usize,
+    #[config(default = 0.0)]
+    pub dropout: f64,
+    #[config(default = false)]
+    pub qkv_bias: bool,
+}
+
+impl CausalAttentionConfig {
+    pub fn validate(&self) -> Result<()> {
+        validate_dropout(self.dropout)
+    }
+
+    pub fn init<B: Backend>(&self, device: &B::Device) -> Result<CausalAttention<B>> {
+        self.validate()?;
+        let linear = LinearConfig::new(self.d_in, self.d_out).with_bias(self.qkv_bias);
+
+        Ok(CausalAttention {
+            w_query: linear.init(device),
+            w_key: linear.init(device),
+            w_value: linear.init(device),
+            dropout: DropoutConfig::new(self.dropout).init(),
+            mask: causal_mask(self.context_length, device),
+        })
+    }
+}
+
+/// Burn's [`DropoutConfig::init`] panics outside `[0, 1]`, and a probability of 1 would scale
+/// what's kept by infinity.
+fn validate_dropout(dropout: f64) -> Result<()> {
+    if !(0.0..1.0).contains(&dropout) {
+        return Err(Error::Config(format!(
+            "dropout ({dropout}) must be in [0, 1)"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Self-attention in which each token only attends to itself and the tokens before it, with
+/// dropout on the attention weights.
+#[derive(Module, Debug)]
+pub struct CausalAttention<B: Backend> {
+    pub w_query: Linear<B>,
+    pub w_key: Linear<B>,
+    pub w_value: Linear<B>,
+    dropout: Dropout,
+    /// `[context_length, context_length]`, and not a parameter, like the book's registered
+    /// buffer.
+    mask: Tensor<B, 2, Bool>,
+}
+
+impl<B: Backend> CausalAttention<B> {
+    /// Maps `[batch, tokens, d_in]` inputs to `[batch, tokens, d_out]` context vectors.
+    pub fn forward(&self, x: Tensor<B, 3>) -> Tensor<B, 3> {
+        let [batch, tokens, _] = x.dims();
+        let queries = self.w_query.forward(x.clone());
+        let keys = self.w_key.forward(x.clone());
+        let values = self.w_value.forward(x);
+
+        let mask = self
+            .mask
+            .clone()
+            .slice([0..tokens, 0..tokens])
+            .unsqueeze::<3>()
+            .expand([batch, tokens, tokens]);
+        let scores = attention_scores(queries, keys).mask_fill(mask, f32::NEG_INFINITY);
+
+        self.dropout.forward(softmax(scores, 2)).matmul(values)
+    }
+}
+
+#[derive(Config, Debug)]
+pub struct MultiHeadAttentionWrapperConfig {
+    pub d_in: usize,
+    /// The size of each head's output; the wrapper's is `num_heads` times this.
+    pub d_out: usize,
+    pub context_length: usize,
+    pub num_heads: usize,
+    #[config(default = 0.0)]
+    pub dropout: f64,
+    #[config(default = false)]
+    pub qkv_bias: bool,
+}
+
+impl MultiHeadAttentionWrapperConfig {
+    pub fn init<B: Backend>(&self, device: &B::Device) -> Result<MultiHeadAttentionWrapper<B>> {
+        let head = CausalAttentionConfig::new(self.d_in, self.d_out, self.context_length)
+            .with_dropout(self.dropout)
+            .with_qkv_bias(self.qkv_bias);
+
+        Ok(MultiHeadAttentionWrapper {
+            heads: (0..self.num_heads)
+                .map(|_| head.init(device))
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+/// The book's first multi-head attention: independent [`CausalAttention`] heads, run one after
+/// the other, with their outputs concatenated. Mostly useful to check [`MultiHeadAttention`]
+/// against.
+#[derive(Module, Debug)]
+pub struct MultiHeadAttentionWrapper<B: Backend> {
+    pub heads: Vec<CausalAttention<B>>,
+}
+
+impl<B: Backend> MultiHeadAttentionWrapper<B> {
+    /// Maps `[batch, tokens, d_in]` inputs to `[batch, tokens, num_heads * d_out]` context
+    /// vectors.
+    pub fn forward(&self, x: Tensor<B, 3>) -> Tensor<B, 3> {
+        let outputs = self
+            .heads
+            .iter()
+            .map(|head| head.forward(x.clone()))
+            .collect();
+
+        Tensor::cat(outputs, 2)
+    }
+
+    /// The same attention as a [`MultiHeadAttention`], with an identity output projection.
+    ///
+    /// # Panics
+    ///
+    /// If the wrapper has no heads.
+    pub fn to_multi_head(&self) -> MultiHeadAttention<B> {
+        let first = self.heads.first().expect("the wrapper has no heads");
+        let [_, head_dim] = first.w_query.weight.dims();
+        let d_out = head_dim * self.heads.len();
+        let device = first.w_query.weight.device();
+
+        // NB: the fused projection's columns are every head's queries, then keys, then values.
+        let linears: Vec<&Linear<B>> = self
+            .heads
+            .iter()
+            .map(|head| &head.w_query)
+            .chain(self.heads.iter().map(|head| &head.w_key))
+            .chain(self.heads.iter().map(|head| &head.w_value))
+            .collect();
+
+        MultiHeadAttention {
+            qkv: fuse(&linears),
+            out_proj: Linear {
+                weight: Param::from_tensor(Tensor::eye(d_out, &device)),
+                bias: Some(Param::from_tensor(Tensor::zeros([d_out], &device))),
+            },
+            dropout: first.dropout.clone(),
+            context_length: first.mask.dims()[0],
+            window: None,
+            num_heads: self.heads.len(),
+            num_kv_groups: self.heads.len(),
+            head_dim,
+            block_size: None,
+            rope: None,
+            alibi: None,
+        }
+    }
+}
+
+/// How [`MultiHeadAttention`] computes its attention weights.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AttentionKernel {
+    /// Softmax over the whole `[tokens, tokens]` score matrix at once, as in the book.
+    Naive,
+    /// Queries and keys `block_size` tokens at a time, with an online softmax, so only a block of
+    /// scores is held at once. The same weights as `Naive` up to rounding, in memory linear in
+    /// the number of tokens, for longer contexts.
+    Chunked { block_size: usize },
+}
+
+#[derive(Config, Debug)]
+pub struct MultiHeadAttentionConfig {
+    pub d_in: usize,
+    /// Split evenly between the heads.
+    pub d_out: usize,
+    pub context_length: usize,
+    pub num_heads: usize,
+    /// Key and value heads, each shared by `num_heads / num_kv_groups` query heads, as in
+    /// grouped-query attention. One group is multi-query attention. Without, each query head has
+    /// its own.
+    pub num_kv_groups: Option<usize>,
+    #[config(default = 0.0)]
+    pub dropout: f64,
+    #[config(default = false)]
+    pub qkv_bias: bool,
+    #[config(default = "AttentionKernel::Naive")]
+    pub kernel: AttentionKernel,
+    /// Rotates the queries and keys by their positions, in place of learned position embeddings.
+    pub rope: Option<RopeConfig>,
+    /// Penalizes each head's scores linearly in how far back the key is, as in ALiBi, in place of
+    /// position embeddings.
+    #[config(default = false)]
+    pub alibi: bool,
+    /// Only attend to the last `window` tokens, including the query itself.
+    pub window: Option<usize>,
+}
+
+impl MultiHeadAttentionConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.num_heads == 0 || !self.d_out.is_multiple_of(self.num_heads) {
+            return Err(Error::Config(format!(
+                "d_out ({}) must be divisible by num_heads ({})",
+                self.d_out, self.num_heads
+            )));
+        }
+        let num_kv_groups = self.num_kv_groups();
+        if num_kv_groups == 0 || !self.num_heads.is_multiple_of(num_kv_groups) {
+            return Err(Error::Config(format!(
+                "num_heads ({}) must be divisible by num_kv_groups ({num_kv_groups})",
+                self.num_heads
+            )));
+        }
+        if self.kernel == (AttentionKernel::Chunked { block_size: 0 }) {
+            return Err(Error::Config("block_size must be positive".to_string()));
+        }
+        if let Some(rope) = &self.rope {
+            rope.validate(self.d_out / self.num_heads)?;
+        }
+        if self.window == Some(0) {
+            return Err(Error::Config("window must be positive".to_string()));
+        }
+        validate_dropout(self.dropout)?;
+
+        Ok(())
+    }
+
+    fn num_kv_groups(&self) -> usize {
+        self.num_kv_groups.unwrap_or(self.num_heads)
+    }
+
+    /// The size of the keys, and of the values.
+    fn kv_dim(&self) -> usize {
+        self.num_kv_groups() * self.d_out / self.num_heads
+    }
+
+    pub fn init<B: Backend>(&self, device: &B::Device) -> Result<MultiHeadAttention<B>> {
+        self.validate()?;
+
+        Ok(MultiHeadAttention {
+            qkv: LinearConfig::new(self.d_in, self.d_out + 2 * self.kv_dim())
+                .with_bias(self.qkv_bias)
+                .init(device),
+            out_proj: LinearConfig::new(self.d_out, self.d_out).init(device),
+            dropout: DropoutConfig::new(self.dropout).init(),
+            context_length: self.context_length,
+            window: self.window,
+            num_heads: self.num_heads,
+            num_kv_groups: self.num_kv_groups(),
+            head_dim: self.d_out / self.num_heads,
+            block_size: match self.kernel {
+                AttentionKernel::Naive => None,
+                AttentionKernel::Chunked { block_size } => Some(block_size),
+            },
+            rope: self
+                .rope
+                .as_ref()
+                .map(|rope| rope.init(self.d_out / self.num_heads, device))
+                .transpose()?,
+            alibi: self.alibi.then(|| {
+                let slopes = alibi_slopes(self.num_heads);
+                Tensor::from_data(TensorData::new(slopes, [self.num_heads]), device)
+            }),
+        })
+    }
+
+    /// Like [`MultiHeadAttentionConfig::init`], with weights loaded from separate projections,
+    /// as most checkpoints store them. The key and value projections are `[d_in, kv_dim]`, where
+    /// `kv_dim` is `d_out` divided by the number of query heads per group.
+    pub fn init_with_projections<B: Backend>(
+        &self,
+        query: Linear<B>,
+        key: Linear<B>,
+        value: Linear<B>,
+        out_proj: Linear<B>,
+    ) -> Result<MultiHeadAttention<B>> {
+        // NB: before anything else, since `kv_dim` divides by `num_heads`.
+        self.validate()?;
+
+        let kv_dim = self.kv_dim();
+        for (name, linear, shape) in [
+            ("query", &query, [self.d_in, self.d_out]),
+            ("key", &key, [self.d_in, kv_dim]),
+            ("value", &value, [self.d_in, kv_dim]),
+            ("output", &out_proj, [self.d_out, self.d_out]),
+        ] {
+            let dims = linear.weight.dims();
+            if dims != shape {
+                return Err(Error::Config(format!(
+                    "expected the {name} projection to be {shape:?}, not {dims:?}"
+                )));
+            }
+        }
+        if [&query, &key, &value]
+            .iter()
+            .any(|linear| linear.bias.is_some() != self.qkv_bias)
+        {
+            return Err(Error::Config(format!(
+                "qkv_bias is {}, but not every projection agrees",
+                self.qkv_bias
+            )));
+        }
+
+        let mut attention = self.init(&query.weight.device())?;
+        attention.qkv = fuse(&[&query, &key, &value]);
+        attention.out_proj = out_proj;
+        Ok(attention)
+    }
+}
+
+/// One projection computing all of `linears`' outputs, one after the other.
+fn fuse<B: Backend>(linears: &[&Linear<B>]) -> Linear<B> {
+    let weight = Tensor::cat(linears.iter().map(|l| l.weight.val()).collect(), 1);
+    let bias = linears
+        .iter()
+        .map(|l| l.bias.as_ref().map(Param::val))
+        .collect::<Option<Vec<_>>>()
+        .map(|biases| Param::from_tensor(Tensor::cat(biases, 0)));
+
+    Linear {
+        weight: Param::from_tensor(weight),
+        bias,
+    }
+}
+
+/// Causal multi-head attention, with every head's queries, keys and values computed by one fused
+/// projection and attended in a single batched `matmul`. With fewer key and value groups than
+/// heads, it's grouped-query attention.
+#[derive(Module, Debug)]
+pub struct MultiHeadAttention<B: Backend> {
+    /// `[d_in, d_out + 2 * kv_dim]`: the queries, then the keys, then the values.
+    pub qkv: Linear<B>,
+    pub out_proj: Linear<B>,
+    dropout: Dropout,
+    // NB: unlike the book's, the causal mask isn't a buffer, but built from the positions of the
+    // scores being masked, so the chunked kernel never holds a `[tokens, tokens]` one.
+    context_length: usize,
+    window: Option<usize>,
+    num_heads: usize,
+    num_kv_groups: usize,
+    head_dim: usize,
+    /// Set for [`AttentionKernel::Chunked`].
+    block_size: Option<usize>,
+    rope: Option<Rope<B>>,
+    /// `[num_heads]`, each head's ALiBi slope.
+    alibi: Option<Tensor<B, 1>>,
+}
+
+impl<B: Backend> MultiHeadAttention<B> {
+    /// Maps `[batch, tokens, d_in]` inputs to `[batch, tokens, d_out]` context vectors.
+    pub fn forward(&self, x: Tensor<B, 3>) -> Tensor<B, 3> {
+        let tokens = x.dims()[1];
+        let (queries, keys, values) = self.heads(x, 0);
+
+        self.attend(queries, keys, values, 0..tokens, None)
+    }
+
+    /// Like [`MultiHeadAttention::forward`], but each query also only attends to the keys that
+    /// `allowed`, `[batch, tokens, tokens]`, is `true` for, such as from [`padding_mask`] or a
+    /// batch's document mask.
+    pub fn forward_masked(&self, x: Tensor<B, 3>, allowed: Tensor<B, 3, Bool>) -> Tensor<B, 3> {
+        let tokens = x.dims()[1];
+        let (queries, keys, values) = self.heads(x, 0);
+
+        self.attend(queries, keys, values, 0..tokens, Some(allowed))
+    }
+
+    /// Like [`MultiHeadAttention::forward`], for `x` continuing the tokens already in `cache`.
+    /// Only `x`'s keys and values are computed, and they're appended to the cache.
+    ///
+    /// # Panics
+    ///
+    /// If `x` has more tokens than the cache can hold.
+    pub fn forward_cached(&self, x: Tensor<B, 3>, cache: &mut KVCache<B>) -> Tensor<B, 3> {
+        let tokens = x.dims()[1];
+        let (queries, keys, values) = self.heads(x, cache.position());
+        let (keys, values) = cache.append(keys, values);
+
+        // NB: the new tokens are the last in the cache, so their positions are the last.
+        let len = cache.len();
+        self.attend(queries, keys, values, len - tokens..len, None)
+    }
+
+    /// An empty cache for up to `max_length` tokens of `batch` sequences, at most the context
+    /// length. It only holds each key and value group once, so with grouped-query attention it's
+    /// `num_heads / num_kv_groups` times smaller.
+    pub fn new_cache(&self, batch: usize, max_length: usize) -> Result<KVCache<B>> {
+        if max_length == 0 || max_length > self.context_length {
+            return Err(Error::Config(format!(
+                "the cache's max_length ({max_length}) must be positive and at most the context \
+                 length ({})",
+                self.context_length
+            )));
+        }
+
+        Ok(KVCache::new(
+            [batch, self.num_kv_groups, max_length, self.head_dim],
+            &self.qkv.weight.device(),
+        ))
+    }
+
+    /// Projects `x`, starting at position `start`, to `[batch, num_heads, tokens, head_dim]`
+    /// queries, and `[batch, num_kv_groups, tokens, head_dim]` keys and values.
+    fn heads(&self, x: Tensor<B, 3>, start: usize) -> (Tensor<B, 4>, Tensor<B, 4>, Tensor<B, 4>) {
+        let [batch, tokens, _] = x.dims();
+        let d_out = self.num_heads * self.head_dim;
+        let kv_dim = self.num_kv_groups * self.head_dim;
+        let qkv = self.qkv.forward(x);
+        let split = |range: Range<usize>, heads: usize| {
+            qkv.clone()
+                .slice(s![.., .., range])
+                .reshape([batch, tokens, heads, self.head_dim])
+                .swap_dims(1, 2)
+        };
+
+        let queries = split(0..d_out, self.num_heads);
+        let keys = split(d_out..d_out + kv_dim, self.num_kv_groups);
+        let values = split(d_out + kv_dim..d_out + 2 * kv_dim, self.num_kv_groups);
+
+        match &self.rope {
+            Some(rope) => (
+                rope.forward(queries, start),
+                rope.forward(keys, start),
+                values,
+            ),
+            None => (queries, keys, values),
+        }
+    }
+
+    /// Attends each query to the keys, as if the queries were at `positions` among them, and
+    /// only to the keys `allowed` lets it.
+    fn attend(
+        &self,
+        queries: Tensor<B, 4>,
+        keys: Tensor<B, 4>,
+        values: Tensor<B, 4>,
+        positions: Range<usize>,
+        allowed: Option<Tensor<B, 3, Bool>>,
+    ) -> Tensor<B, 3> {
+        let [batch, _, tokens, _] = queries.dims();
+        let (keys, values) = (self.repeat_kv(keys), self.repeat_kv(values));
+        let context = match self.block_size {
+            None => self.attend_naive(queries, keys, values, positions, allowed),
+            Some(block_size) => {
+                self.attend_chunked(queries, keys, values, positions, allowed, block_size)
+            }
+        };
+
+        self.out_proj.forward(context.swap_dims(1, 2).reshape([
+            batch,
+            tokens,
+            self.num_heads * self.head_dim,
+        ]))
+    }
+
+    /// Shares each group's `[batch, num_kv_groups, tokens, head_dim]` keys or values with its
+    /// query heads, as `[batch, num_heads, tokens, head_dim]`.
+    fn repeat_kv(&self, x: Tensor<B, 4>) -> Tensor<B, 4> {
+        if self.num_kv_groups == self.num_heads {
+            return x;
+        }
+
+        let [batch, groups, tokens, head_dim] = x.dims();
+        x.unsqueeze_dim::<5>(2)
+            .expand([batch, groups, self.num_heads / groups, tokens, head_dim])
+            .reshape([batch, self.num_heads, tokens, head_dim])
+    }
+
+    /// The biased and masked scores of queries at `rows` among the keys for keys at `cols`, with
+    /// `fill` in place of masked scores. `allowed` is `[batch, rows, cols]`.
+    fn scores(
+        &self,
+        queries: Tensor<B, 4>,
+        keys: Tensor<B, 4>,
+        rows: Range<usize>,
+        cols: Range<usize>,
+        allowed: Option<Tensor<B, 3, Bool>>,
+        fill: f32,
+    ) -> Tensor<B, 4> {
+        let [batch, num_heads, tokens, _] = queries.dims();
+        let keys_len = cols.len();
+        let shape = [batch, num_heads, tokens, keys_len];
+        let device = queries.device();
+
+        // `[tokens, keys]`, how far back each key is from each query.
+        let distances = Tensor::<B, 1, Int>::arange(rows.start as i64..rows.end as i64, &device)
+            .reshape([tokens, 1])
+            .expand([tokens, keys_len])
+            - Tensor::<B, 1, Int>::arange(cols.start as i64..cols.end as i64, &device)
+                .reshape([1, keys_len])
+                .expand([tokens, keys_len]);
+
+        let mut scores = attention_scores(queries, keys);
+        if let Some(slopes) = &self.alibi {
+            let bias = slopes.clone().reshape([num_heads, 1, 1])
+                * distances.clone().float().unsqueeze::<3>();
+            scores = scores - bias.unsqueeze::<4>();
+        }
+
+        // `true` where a key comes after its query, or, with a window, too long before it.
+        let mut mask = distances.clone().lower_elem(0);
+        if let Some(window) = self.window {
+            mask = mask.bool_or(distances.clone().greater_equal_elem(window as i64));
+        }
+        let mut mask = mask.unsqueeze::<4>().expand(shape);
+        if let Some(allowed) = allowed {
+            // NB: a query may always attend to itself, so that a padded one isn't left with
+            // nothing to attend to, and a row of NaNs.
+            let allowed = allowed.bool_or(
+                distances
+                    .equal_elem(0)
+                    .unsqueeze::<3>()
+                    .expand([batch, tokens, keys_len]),
+            );
+            mask = mask.bool_or(allowed.bool_not().unsqueeze_dim::<4>(1).expand(shape));
+        }
+
+        scores.mask_fill(mask, fill)
+    }
+
+    fn attend_naive(
+        &self,
+        queries: Tensor<B, 4>,
+        keys: Tensor<B, 4>,
+        values: Tensor<B, 4>,
+        positions: Range<usize>,
+        allowed: Option<Tensor<B, 3, Bool>>,
+    ) -> Tensor<B, 4> {
+        let keys_len = keys.dims()[2];
+        let scores = self.scores(
+            queries,
+            keys,
+            positions,
+            0..keys_len,
+            allowed,
+            f32::NEG_INFINITY,
+        );
+
+        self.dropout.forward(softmax(scores, 3)).matmul(values)
+    }
+
+    /// Like [`MultiHeadAttention::attend_naive`], a block of queries at a time. Each block keeps
+    /// a running maximum score, softmax denominator and weighted sum of values, rescaling them
+    /// whenever a block of keys raises the maximum, so the scores for all the keys never need to
+    /// be held at once.
+    fn attend_chunked(
+        &self,
+        queries: Tensor<B, 4>,
+        keys: Tensor<B, 4>,
+        values: Tensor<B, 4>,
+        positions: Range<usize>,
+        allowed: Option<Tensor<B, 3, Bool>>,
+        block_size: usize,
+    ) -> Tensor<B, 4> {
+        let [batch, num_heads, tokens, head_dim] = queries.dims();
+        let keys_len = keys.dims()[2];
+        let device = queries.device();
+
+        let blocks = (0..tokens).step_by(block_size).map(|start| {
+            let end = (start + block_size).min(tokens);
+            let rows = positions.start + start..positions.start + end;
+            let queries = queries.clone().slice(s![.., .., start..end]);
+
+            let shape = [batch, num_heads, end - start, 1];
+            let mut max = Tensor::<B, 4>::full(shape, f32::NEG_INFINITY, &device);
+            let mut sum = Tensor::<B, 4>::zeros(shape, &device);
+            let mut context =
+                Tensor::<B, 4>::zeros([batch, num_heads, end - start, head_dim], &device);
+
+            // NB: keys after the block's last query are all masked, so they're skipped.
+            for key_start in (0..keys_len.min(rows.end)).step_by(block_size) {
+                let key_end = (key_start + block_size).min(keys_len);
+                // NB: a finite fill, unlike the naive path's, so that a row whose keys in this
+                // block are all masked doesn't make its running maximum -inf, and its update NaN.
+                let scores = self.scores(
+                    queries.clone(),
+                    keys.clone().slice(s![.., .., key_start..key_end]),
+                    rows.clone(),
+                    key_start..key_end,
+                    allowed
+                        .clone()
+                        .map(|allowed| allowed.slice(s![.., start..end, key_start..key_end])),
+                    f32::MIN,
+                );
+
+                let new_max = max.clone().max_pair(scores.clone().max_dim(3));
+                let rescale = (max - new_max.clone()).exp();
+                let weights = (scores - new_max.clone()).exp();
+
+                sum = sum * rescale.clone() + weights.clone().sum_dim(3);
+                context = context * rescale
+                    + self
+                        .dropout
+                        .forward(weights)
+                        .matmul(values.clone().slice(s![.., .., key_start..key_end]));
+                max = new_max;
+            }
+
+            context / sum
+        });
+
+        Tensor::cat(blocks.collect(), 2)
+    }
+}
+
+/// `[batch, tokens, tokens]` from a `[batch, tokens]` padding mask, such as
+/// `GPTDatasetBatch::attention_mask`, for [`MultiHeadAttention::forward_masked`]: `true` where
+/// the key isn't padding. Combine it with a document mask with `bool_and`.
+pub fn padding_mask<B: Backend>(attention_mask: Tensor<B, 2, Bool>) -> Tensor<B, 3, Bool> {
+    let [batch, tokens] = attention_mask.dims();
+    attention_mask
+        .unsqueeze_dim::<3>(1)
+        .expand([batch, tokens, tokens])
+}
+
+/// ALiBi's slope for each head: a geometric sequence from `2^(-8 / num_heads)`, with every other
+/// slope of the next power of two's sequence added for head counts that aren't one.
+fn alibi_slopes(num_heads: usize) -> Vec<f32> {
+    let slopes = |n: usize| (1..=n).map(move |i| 2f32.powf(-8.0 * i as f32 / n as f32));
+    let closest = 1 << num_heads.ilog2();
+
+    slopes(closest)
+        .chain(slopes(2 * closest).step_by(2).take(num_heads - closest))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
-    use burn::backend::NdArray;
+    use burn::backend::{LibTorch, NdArray, Wgpu, libtorch::LibTorchDevice, wgpu::WgpuDevice};
+    use std::ops::Range;
+
+    use burn::module::Param;
+    use burn::nn::{Linear, LinearConfig};
+    use burn::prelude::Backend as BackendTrait;
     use burn::tensor::activation::softmax;
-    use burn::tensor::{Int, TensorData, Tolerance, s};
+    use burn::tensor::{Bool, Distribution, Int, TensorData, Tolerance, s};
     use burn::{Tensor, backend::ndarray::NdArrayDevice};
     use log::info;
     use rstest::{fixture, rstest};
 
+    use crate::{
+        error::Error,
+        listings::ch03::{
+            AttentionKernel, CausalAttentionConfig, MultiHeadAttentionConfig,
+            MultiHeadAttentionWrapperConfig, SelfAttentionV1, SelfAttentionV1Config,
+            SelfAttentionV2Config, alibi_slopes, padding_mask,
+        },
+    };
+
+    /// The book's "Your journey starts with one step" embeddings.
+    fn inputs<B: BackendTrait>(device: &B::Device) -> Tensor<B, 2> {
+        Tensor::from_floats(
+            [
+                [0.43, 0.15, 0.89],
+                [0.55, 0.87, 0.66],
+                [0.57, 0.85, 0.64],
+                [0.22, 0.58, 0.33],
+                [0.77, 0.25, 0.10],
+                [0.05, 0.80, 0.55],
+            ],
+            device,
+        )
+    }
+
+    /// The book's context vectors from the unweighted self-attention of section 3.3.
+    const ALL_CONTEXT_VECS: [[f32; 3]; 6] = [
+        [0.4421, 0.5931, 0.5790],
+        [0.4419, 0.6515, 0.5683],
+        [0.4431, 0.6496, 0.5671],
+        [0.4304, 0.6298, 0.5510],
+        [0.4671, 0.5910, 0.5266],
+        [0.4177, 0.6503, 0.5645],
+    ];
+
     #[fixture]
     #[once]
     fn init_logger() -> () {
@@ -135,4 +916,433 @@ mod tests {
             Tolerance::<f32>::balanced(),
         );
     }
+
+    #[test]
+    fn test_self_attention_v1() {
+        let device = &NdArrayDevice::Cpu;
+        let mut attention = SelfAttentionV1Config::new(3, 3).init::<NdArray>(device);
+        assert_eq!(attention.w_query.dims(), [3, 3]);
+
+        // NB: scaling the queries and keys by the fourth root of d_k cancels out the scaling of
+        // the scores, which leaves the book's unweighted attention.
+        let scale = 3f32.powf(0.25);
+        attention.w_query = Param::from_tensor(Tensor::eye(3, device) * scale);
+        attention.w_key = Param::from_tensor(Tensor::eye(3, device) * scale);
+        attention.w_value = Param::from_tensor(Tensor::eye(3, device));
+
+        attention
+            .forward(inputs(device))
+            .to_data()
+            .assert_approx_eq(
+                &TensorData::from(ALL_CONTEXT_VECS),
+                Tolerance::<f32>::absolute(1e-4),
+            );
+    }
+
+    #[test]
+    fn test_self_attention_v2() {
+        let device = &NdArrayDevice::Cpu;
+        let v2 = SelfAttentionV2Config::new(3, 2).init::<NdArray>(device);
+
+        // Exercise 3.1: V1 with V2's weights gives the same context vectors.
+        let v1 = SelfAttentionV1 {
+            w_query: Param::from_tensor(v2.w_query.weight.val()),
+            w_key: Param::from_tensor(v2.w_key.weight.val()),
+            w_value: Param::from_tensor(v2.w_value.weight.val()),
+        };
+
+        let context = v2.forward(inputs(device));
+        assert_eq!(context.dims(), [6, 2]);
+        context.to_data().assert_approx_eq(
+            &v1.forward(inputs(device)).to_data(),
+            Tolerance::<f32>::default(),
+        );
+    }
+
+    #[test]
+    fn test_causal_attention() {
+        let device = &NdArrayDevice::Cpu;
+        let mut attention = CausalAttentionConfig::new(3, 3, 6)
+            .with_dropout(0.5)
+            .init::<NdArray>(device)
+            .unwrap();
+        let scale = 3f32.powf(0.25);
+        attention.w_query.weight = Param::from_tensor(Tensor::eye(3, device) * scale);
+        attention.w_key.weight = Param::from_tensor(Tensor::eye(3, device) * scale);
+        attention.w_value.weight = Param::from_tensor(Tensor::eye(3, device));
+        for linear in [
+            &mut attention.w_query,
+            &mut attention.w_key,
+            &mut attention.w_value,
+        ] {
+            linear.bias = None;
+        }
+
+        let batch = Tensor::stack::<3>(vec![inputs(device), inputs(device)], 0);
+        let context = attention.forward(batch);
+        assert_eq!(context.dims(), [2, 6, 3]);
+
+        // NB: dropout only applies while training, on an autodiff backend.
+        let first = context.clone().slice([0..1, 0..1]);
+        first.to_data().assert_approx_eq(
+            &TensorData::from([[[0.43f32, 0.15, 0.89]]]),
+            Tolerance::<f32>::absolute(1e-4),
+        );
+        // The last token attends to every token, as without the mask.
+        let last = context.slice([1..2, 5..6]);
+        last.to_data().assert_approx_eq(
+            &TensorData::from([[ALL_CONTEXT_VECS[5]]]),
+            Tolerance::<f32>::absolute(1e-4),
+        );
+
+        for dropout in [-0.1, 1.0] {
+            assert!(matches!(
+                CausalAttentionConfig::new(3, 3, 6)
+                    .with_dropout(dropout)
+                    .init::<NdArray>(device),
+                Err(Error::Config(_))
+            ));
+        }
+    }
+
+    fn multi_head_attention<B: BackendTrait>(device: &B::Device) {
+        let wrapper = MultiHeadAttentionWrapperConfig::new(3, 2, 6, 2)
+            .with_qkv_bias(true)
+            .init::<B>(device)
+            .unwrap();
+        let batch = Tensor::stack::<3>(vec![inputs(device), inputs(device)], 0);
+
+        let expected = wrapper.forward(batch.clone());
+        assert_eq!(expected.dims(), [2, 6, 4]);
+        let fused = wrapper.to_multi_head();
+        fused
+            .forward(batch.clone())
+            .to_data()
+            .assert_approx_eq(&expected.to_data(), Tolerance::<f32>::absolute(1e-5));
+
+        // The book's `MultiHeadAttention(d_in, d_out=2, context_length, 0.0, num_heads=2)`.
+        let attention = MultiHeadAttentionConfig::new(3, 2, 6, 2)
+            .init::<B>(device)
+            .unwrap();
+        let context = attention.forward(batch.clone());
+        assert_eq!(context.dims(), [2, 6, 2]);
+        context.clone().slice(s![0..1]).to_data().assert_approx_eq(
+            &context.slice(s![1..2]).to_data(),
+            Tolerance::<f32>::default(),
+        );
+
+        // Later tokens don't change earlier tokens' context vectors.
+        fused
+            .forward(batch.slice([0..2, 0..3]))
+            .to_data()
+            .assert_approx_eq(
+                &expected.slice([0..2, 0..3]).to_data(),
+                Tolerance::<f32>::absolute(1e-5),
+            );
+    }
+
+    #[test]
+    fn test_multi_head_attention() {
+        multi_head_attention::<NdArray>(&NdArrayDevice::Cpu);
+
+        assert!(matches!(
+            MultiHeadAttentionConfig::new(3, 4, 6, 3).validate(),
+            Err(Error::Config(_))
+        ));
+        for dropout in [-0.1, 1.0, f64::NAN] {
+            assert!(matches!(
+                MultiHeadAttentionConfig::new(3, 4, 6, 2)
+                    .with_dropout(dropout)
+                    .validate(),
+                Err(Error::Config(_))
+            ));
+        }
+    }
+
+    #[rstest]
+    #[case(1)]
+    #[case(3)]
+    #[case(4)]
+    #[case(16)]
+    fn test_chunked_attention(#[case] block_size: usize) {
+        let device = &NdArrayDevice::Cpu;
+        let config = MultiHeadAttentionConfig::new(4, 6, 16, 2).with_qkv_bias(true);
+        let naive = config.init::<NdArray>(device).unwrap();
+        let mut chunked = naive.clone();
+        chunked.block_size = Some(block_size);
+
+        let x = Tensor::<NdArray, 3>::random([2, 10, 4], Distribution::Uniform(-4.0, 4.0), device);
+        let expected = naive.forward(x.clone());
+        chunked
+            .forward(x.clone())
+            .into_data()
+            .assert_approx_eq(&expected.to_data(), Tolerance::<f32>::absolute(1e-5));
+
+        let mut cache = chunked.new_cache(2, 16).unwrap();
+        chunked.forward_cached(x.clone().slice(s![.., 0..7]), &mut cache);
+        chunked
+            .forward_cached(x.slice(s![.., 7..10]), &mut cache)
+            .into_data()
+            .assert_approx_eq(
+                &expected.slice(s![.., 7..10]).into_data(),
+                Tolerance::<f32>::absolute(1e-5),
+            );
+
+        let config = config.with_kernel(AttentionKernel::Chunked { block_size });
+        assert_eq!(
+            config.init::<NdArray>(device).unwrap().block_size,
+            Some(block_size)
+        );
+        assert!(matches!(
+            config
+                .with_kernel(AttentionKernel::Chunked { block_size: 0 })
+                .validate(),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_alibi_slopes() {
+        let powers =
+            |exponents: &[i32]| -> Vec<f32> { exponents.iter().map(|e| 2f32.powi(-e)).collect() };
+        assert_eq!(alibi_slopes(8), powers(&[1, 2, 3, 4, 5, 6, 7, 8]));
+        assert_eq!(alibi_slopes(6), powers(&[2, 4, 6, 8, 1, 3]));
+    }
+
+    #[rstest]
+    #[case(AttentionKernel::Naive)]
+    #[case(AttentionKernel::Chunked { block_size: 2 })]
+    fn test_attention_biases(#[case] kernel: AttentionKernel) {
+        let device = &NdArrayDevice::Cpu;
+        let config = MultiHeadAttentionConfig::new(4, 4, 8, 2).with_kernel(kernel);
+        let x = Tensor::<NdArray, 3>::random([1, 6, 4], Distribution::Default, device);
+        let tolerance = Tolerance::<f32>::absolute(1e-5);
+
+        // With a window of three, the last token attends as if the first three weren't there.
+        let windowed = config
+            .clone()
+            .with_window(Some(3))
+            .with_alibi(true)
+            .init::<NdArray>(device)
+            .unwrap();
+        let mut unwindowed = windowed.clone();
+        unwindowed.window = None;
+        windowed
+            .forward(x.clone())
+            .slice(s![.., 5..6])
+            .into_data()
+            .assert_approx_eq(
+                &unwindowed
+                    .forward(x.clone().slice(s![.., 3..6]))
+                    .slice(s![.., 2..3])
+                    .into_data(),
+                tolerance,
+            );
+
+        // ALiBi's biases only depend on how far apart tokens are, so they work with a cache.
+        let alibi = config
+            .clone()
+            .with_alibi(true)
+            .init::<NdArray>(device)
+            .unwrap();
+        let mut cache = alibi.new_cache(1, 4).unwrap();
+        let mut last = None;
+        for i in 0..6 {
+            last = Some(alibi.forward_cached(x.clone().slice(s![.., i..i + 1]), &mut cache));
+        }
+        last.unwrap().into_data().assert_approx_eq(
+            &alibi
+                .forward(x.clone().slice(s![.., 2..6]))
+                .slice(s![.., 3..4])
+                .into_data(),
+            tolerance,
+        );
+        let mut unbiased = alibi.clone();
+        unbiased.alibi = None;
+        assert!(
+            (alibi.forward(x.clone()) - unbiased.forward(x.clone()))
+                .abs()
+                .max()
+                .into_scalar()
+                > 1e-3
+        );
+    }
+
+    #[rstest]
+    #[case(AttentionKernel::Naive)]
+    #[case(AttentionKernel::Chunked { block_size: 3 })]
+    fn test_attention_padding_and_documents(#[case] kernel: AttentionKernel) {
+        let device = &NdArrayDevice::Cpu;
+        let attention = MultiHeadAttentionConfig::new(4, 4, 8, 2)
+            .with_kernel(kernel)
+            .with_alibi(true)
+            .init::<NdArray>(device)
+            .unwrap();
+        let tolerance = Tolerance::<f32>::absolute(1e-5);
+
+        // The second item packs two documents, and the first is two tokens of padding and then
+        // the second item's second document.
+        let tokens = Tensor::<NdArray, 3>::random([1, 4, 4], Distribution::Default, device);
+        let padded = Tensor::cat(
+            vec![
+                Tensor::zeros([1, 2, 4], device),
+                tokens.clone().slice(s![.., 2..4]),
+            ],
+            1,
+        );
+        let x = Tensor::cat(vec![padded, tokens.clone()], 0);
+
+        let attention_mask = Tensor::<NdArray, 2, Bool>::from_data(
+            TensorData::from([[false, false, true, true], [true, true, true, true]]),
+            device,
+        );
+        let document_mask = Tensor::<NdArray, 3, Bool>::from_data(
+            TensorData::from([
+                [[true; 4]; 4],
+                [
+                    [true, true, false, false],
+                    [true, true, false, false],
+                    [false, false, true, true],
+                    [false, false, true, true],
+                ],
+            ]),
+            device,
+        );
+
+        let context = attention.forward_masked(
+            x.clone(),
+            padding_mask(attention_mask).bool_and(document_mask),
+        );
+        assert!(!context.clone().is_nan().any().into_scalar());
+        let alone = attention.forward(tokens.slice(s![.., 2..4]));
+        context
+            .clone()
+            .slice(s![0..1, 2..4])
+            .into_data()
+            .assert_approx_eq(&alone.to_data(), tolerance);
+        context
+            .slice(s![1..2, 2..4])
+            .into_data()
+            .assert_approx_eq(&alone.into_data(), tolerance);
+    }
+
+    #[rstest]
+    #[case(1)]
+    #[case(2)]
+    #[case(4)]
+    fn test_grouped_query_attention(#[case] num_kv_groups: usize) {
+        let device = &NdArrayDevice::Cpu;
+        let config = MultiHeadAttentionConfig::new(4, 8, 8, 4)
+            .with_qkv_bias(true)
+            .with_num_kv_groups(Some(num_kv_groups));
+        let grouped = config.init::<NdArray>(device).unwrap();
+        let kv_dim = 2 * num_kv_groups;
+        assert_eq!(grouped.qkv.weight.dims(), [4, 8 + 2 * kv_dim]);
+
+        // The same attention as multi-head attention with each group's keys and values repeated
+        // for each of its heads.
+        let repeat = |range: Range<usize>| {
+            let weight = grouped.qkv.weight.val().slice(s![.., range.clone()]);
+            let bias = grouped.qkv.bias.as_ref().unwrap().val().slice(range);
+            let heads_per_group = 4 / num_kv_groups;
+            Linear {
+                weight: Param::from_tensor(
+                    weight
+                        .reshape([4, num_kv_groups, 1, 2])
+                        .expand([4, num_kv_groups, heads_per_group, 2])
+                        .reshape([4, 8]),
+                ),
+                bias: Some(Param::from_tensor(
+                    bias.reshape([num_kv_groups, 1, 2])
+                        .expand([num_kv_groups, heads_per_group, 2])
+                        .reshape([8]),
+                )),
+            }
+        };
+        let query = Linear {
+            weight: Param::from_tensor(grouped.qkv.weight.val().slice(s![.., 0..8])),
+            bias: Some(Param::from_tensor(
+                grouped.qkv.bias.as_ref().unwrap().val().slice(0..8),
+            )),
+        };
+        let multi_head = config
+            .clone()
+            .with_num_kv_groups(None)
+            .init_with_projections(
+                query,
+                repeat(8..8 + kv_dim),
+                repeat(8 + kv_dim..8 + 2 * kv_dim),
+                grouped.out_proj.clone(),
+            )
+            .unwrap();
+
+        let x = Tensor::<NdArray, 3>::random([2, 5, 4], Distribution::Default, device);
+        grouped.forward(x.clone()).into_data().assert_approx_eq(
+            &multi_head.forward(x).into_data(),
+            Tolerance::<f32>::absolute(1e-5),
+        );
+    }
+
+    #[test]
+    fn test_grouped_query_attention_config() {
+        let device = &NdArrayDevice::Cpu;
+        let config = MultiHeadAttentionConfig::new(4, 8, 8, 4).with_num_kv_groups(Some(2));
+
+        for groups in [0, 3] {
+            assert!(matches!(
+                config.clone().with_num_kv_groups(Some(groups)).validate(),
+                Err(Error::Config(_))
+            ));
+        }
+
+        // The key and value projections are [d_in, kv_dim], here [4, 4].
+        let linear = |d_in, d_out| LinearConfig::new(d_in, d_out).with_bias(false).init(device);
+        config
+            .init_with_projections::<NdArray>(
+                linear(4, 8),
+                linear(4, 4),
+                linear(4, 4),
+                linear(8, 8),
+            )
+            .unwrap();
+        assert!(matches!(
+            config.init_with_projections::<NdArray>(
+                linear(4, 8),
+                linear(4, 8),
+                linear(4, 8),
+                linear(8, 8)
+            ),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            MultiHeadAttentionConfig::new(4, 8, 8, 0).init_with_projections::<NdArray>(
+                linear(4, 8),
+                linear(4, 4),
+                linear(4, 4),
+                linear(8, 8)
+            ),
+            Err(Error::Config(_))
+        ));
+        assert!(matches!(
+            config.with_qkv_bias(true).init_with_projections::<NdArray>(
+                linear(4, 8),
+                linear(4, 4),
+                linear(4, 4),
+                linear(8, 8)
+            ),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[test]
+    #[cfg_attr(feature = "ci", ignore)]
+    fn test_multi_head_attention_wgpu() {
+        multi_head_attention::<Wgpu>(&WgpuDevice::default());
+    }
+
+    #[test]
+    #[cfg_attr(feature = "ci", ignore)]
+    fn test_multi_head_attention_tch() {
+        multi_head_attention::<LibTorch>(&LibTorchDevice::Cpu);
+    }
 }
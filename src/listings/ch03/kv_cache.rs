@@ -0,0 +1,241 @@
+use burn::{
+    prelude::Backend,
+    tensor::{Tensor, s},
+};
+
+/// The keys and values of the tokens an attention layer has already seen, so that generating each
+/// new token only computes its own. Space for `max_length` tokens is allocated up front; once it's
+/// full, the oldest tokens slide out to make room for new ones.
+#[derive(Clone, Debug)]
+pub struct KVCache<B: Backend> {
+    /// `[batch, num_kv_groups, max_length, head_dim]`, of which the first `len` tokens are set.
+    keys: Tensor<B, 4>,
+    values: Tensor<B, 4>,
+    len: usize,
+    /// Tokens appended since the last reset, including any that have slid out.
+    seen: usize,
+}
+
+impl<B: Backend> KVCache<B> {
+    /// An empty cache of `[batch, num_kv_groups, max_length, head_dim]`.
+    pub fn new(shape: [usize; 4], device: &B::Device) -> Self {
+        Self {
+            keys: Tensor::zeros(shape, device),
+            values: Tensor::zeros(shape, device),
+            len: 0,
+            seen: 0,
+        }
+    }
+
+    /// The number of tokens cached.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn max_length(&self) -> usize {
+        self.keys.dims()[2]
+    }
+
+    /// The position of the next token in its sequence, counting tokens that have slid out.
+    pub fn position(&self) -> usize {
+        self.seen
+    }
+
+    /// Empties the cache for a new prompt, keeping its memory.
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.seen = 0;
+    }
+
+    /// Appends `[batch, num_kv_groups, tokens, head_dim]` keys and values, dropping the oldest
+    /// tokens if they don't fit, and returns everything cached.
+    ///
+    /// # Panics
+    ///
+    /// If there are more new tokens than the cache can hold.
+    pub fn append(
+        &mut self,
+        keys: Tensor<B, 4>,
+        values: Tensor<B, 4>,
+    ) -> (Tensor<B, 4>, Tensor<B, 4>) {
+        let tokens = keys.dims()[2];
+        let max_length = self.max_length();
+        assert!(
+            tokens <= max_length,
+            "{tokens} tokens don't fit in a cache of {max_length}"
+        );
+
+        let overflow = (self.len + tokens).saturating_sub(max_length);
+        // NB: if none of the cached tokens are kept, there's nothing to slide.
+        let kept = self.len - overflow;
+        if overflow > 0 && kept > 0 {
+            let slide = |cache: Tensor<B, 4>| {
+                let tail = cache.clone().slice(s![.., .., overflow..self.len]);
+                cache.slice_assign(s![.., .., 0..kept], tail)
+            };
+            self.keys = slide(self.keys.clone());
+            self.values = slide(self.values.clone());
+        }
+        self.len = kept;
+
+        let end = self.len + tokens;
+        self.keys = self
+            .keys
+            .clone()
+            .slice_assign(s![.., .., self.len..end], keys);
+        self.values = self
+            .values
+            .clone()
+            .slice_assign(s![.., .., self.len..end], values);
+        self.len = end;
+        self.seen += tokens;
+
+        (
+            self.keys.clone().slice(s![.., .., 0..end]),
+            self.values.clone().slice(s![.., .., 0..end]),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::{
+        Tensor,
+        backend::{NdArray, ndarray::NdArrayDevice},
+        tensor::{Distribution, Tolerance, s},
+    };
+
+    use rstest::rstest;
+
+    use crate::{
+        error::Error,
+        listings::ch03::{
+            MultiHeadAttentionConfig,
+            kv_cache::KVCache,
+            rope::{RopeConfig, RopeScaling},
+        },
+    };
+
+    #[test]
+    fn test_kv_cache_append() {
+        let device = &NdArrayDevice::Cpu;
+        let mut cache = KVCache::<NdArray>::new([1, 1, 3, 1], device);
+        let token = |i: i32| {
+            Tensor::<NdArray, 1, _>::from_ints([i], device)
+                .float()
+                .reshape([1, 1, 1, 1])
+        };
+
+        for i in 0..2 {
+            cache.append(token(i), token(i));
+        }
+        assert_eq!(cache.len(), 2);
+
+        // The cache is full after one more token, and then slides.
+        let (keys, _) = cache.append(token(2), token(2));
+        assert_eq!(keys.into_data().to_vec::<f32>().unwrap(), [0.0, 1.0, 2.0]);
+        let (keys, values) = cache.append(token(3), token(3));
+        assert_eq!(keys.into_data().to_vec::<f32>().unwrap(), [1.0, 2.0, 3.0]);
+        assert_eq!(values.into_data().to_vec::<f32>().unwrap(), [1.0, 2.0, 3.0]);
+        assert_eq!((cache.len(), cache.position()), (3, 4));
+
+        cache.reset();
+        assert!(cache.is_empty());
+        let (keys, _) = cache.append(token(7), token(7));
+        assert_eq!(keys.into_data().to_vec::<f32>().unwrap(), [7.0]);
+
+        // A chunk as long as the cache replaces everything in it.
+        let chunk =
+            Tensor::<NdArray, 1>::from_floats([4.0, 5.0, 6.0], device).reshape([1, 1, 3, 1]);
+        let (keys, _) = cache.append(chunk.clone(), chunk);
+        assert_eq!(keys.into_data().to_vec::<f32>().unwrap(), [4.0, 5.0, 6.0]);
+        assert_eq!((cache.len(), cache.position()), (3, 4));
+    }
+
+    #[rstest]
+    #[case(None)]
+    #[case(Some(RopeConfig::new()))]
+    #[case(Some(RopeConfig::new().with_scaling(Some(RopeScaling::new().with_original_context_length(4)))))]
+    fn test_cached_attention(#[case] rope: Option<RopeConfig>) {
+        let device = &NdArrayDevice::Cpu;
+        let attention = MultiHeadAttentionConfig::new(4, 8, 8, 2)
+            .with_qkv_bias(true)
+            .with_rope(rope)
+            .init::<NdArray>(device)
+            .unwrap();
+        let x = Tensor::<NdArray, 3>::random([2, 8, 4], Distribution::Default, device);
+        let expected = attention.forward(x.clone());
+
+        let mut cache = attention.new_cache(2, 8).unwrap();
+        for _ in 0..2 {
+            cache.reset();
+            // A prompt of three tokens, then one token at a time.
+            let mut outputs =
+                vec![attention.forward_cached(x.clone().slice(s![.., 0..3]), &mut cache)];
+            for i in 3..8 {
+                outputs
+                    .push(attention.forward_cached(x.clone().slice(s![.., i..i + 1]), &mut cache));
+            }
+
+            Tensor::cat(outputs, 1)
+                .into_data()
+                .assert_approx_eq(&expected.to_data(), Tolerance::<f32>::absolute(1e-6));
+        }
+
+        assert!(matches!(attention.new_cache(2, 9), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn test_multi_query_cache() {
+        let device = &NdArrayDevice::Cpu;
+        let attention = MultiHeadAttentionConfig::new(4, 8, 8, 4)
+            .with_num_kv_groups(Some(1))
+            .with_rope(Some(RopeConfig::new()))
+            .init::<NdArray>(device)
+            .unwrap();
+        let x = Tensor::<NdArray, 3>::random([2, 5, 4], Distribution::Default, device);
+
+        let mut cache = attention.new_cache(2, 8).unwrap();
+        attention.forward_cached(x.clone().slice(s![.., 0..2]), &mut cache);
+        let (keys, _) = cache.clone().append(
+            Tensor::zeros([2, 1, 1, 2], device),
+            Tensor::zeros([2, 1, 1, 2], device),
+        );
+        // One key and value head, shared by all four query heads.
+        assert_eq!(keys.dims(), [2, 1, 3, 2]);
+
+        attention
+            .forward_cached(x.clone().slice(s![.., 2..5]), &mut cache)
+            .into_data()
+            .assert_approx_eq(
+                &attention.forward(x).slice(s![.., 2..5]).into_data(),
+                Tolerance::<f32>::absolute(1e-6),
+            );
+    }
+
+    #[test]
+    fn test_sliding_attention() {
+        let device = &NdArrayDevice::Cpu;
+        let attention = MultiHeadAttentionConfig::new(4, 4, 8, 2)
+            .init::<NdArray>(device)
+            .unwrap();
+        let x = Tensor::<NdArray, 3>::random([1, 6, 4], Distribution::Default, device);
+
+        let mut cache = attention.new_cache(1, 4).unwrap();
+        let mut last = None;
+        for i in 0..6 {
+            last = Some(attention.forward_cached(x.clone().slice(s![.., i..i + 1]), &mut cache));
+        }
+
+        // Once the cache has slid, the last token only attends to the last four tokens.
+        let window = attention.forward(x.slice(s![.., 2..6]));
+        last.unwrap().into_data().assert_approx_eq(
+            &window.slice(s![.., 3..4]).into_data(),
+            Tolerance::<f32>::absolute(1e-6),
+        );
+    }
+}
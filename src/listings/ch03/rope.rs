@@ -0,0 +1,199 @@
+use std::f64::consts::PI;
+
+use burn::{
+    config::Config,
+    module::Module,
+    prelude::Backend,
+    tensor::{Int, Tensor, TensorData, s},
+};
+
+use crate::error::{Error, Result};
+
+/// Llama 3's stretching of the lower rotary frequencies, for contexts longer than the model was
+/// first trained on. The defaults are Llama 3.1's.
+#[derive(Config, Debug, PartialEq)]
+pub struct RopeScaling {
+    /// How much longer the context gets, i.e. how far the lowest frequencies are divided.
+    #[config(default = 8.0)]
+    pub factor: f64,
+    /// Frequencies with wavelengths longer than `original_context_length / low_freq_factor` are
+    /// divided by `factor`.
+    #[config(default = 1.0)]
+    pub low_freq_factor: f64,
+    /// Frequencies with wavelengths shorter than `original_context_length / high_freq_factor` are
+    /// left alone, and those in between are interpolated.
+    #[config(default = 4.0)]
+    pub high_freq_factor: f64,
+    #[config(default = 8192)]
+    pub original_context_length: usize,
+}
+
+/// Rotary position embeddings, which rotate each pair of a query's or key's dimensions by an angle
+/// proportional to its position, so that attention scores depend on how far apart tokens are.
+#[derive(Config, Debug, PartialEq)]
+pub struct RopeConfig {
+    /// The wavelength of the lowest frequency, over 2π. GPT-NeoX and Llama 2 use 10,000, Llama 3
+    /// 500,000.
+    #[config(default = 10000.0)]
+    pub theta_base: f64,
+    pub scaling: Option<RopeScaling>,
+}
+
+impl RopeConfig {
+    pub fn validate(&self, head_dim: usize) -> Result<()> {
+        if head_dim == 0 || !head_dim.is_multiple_of(2) {
+            return Err(Error::Config(format!(
+                "rotary embeddings need an even head_dim, not {head_dim}"
+            )));
+        }
+        if let Some(scaling) = &self.scaling
+            && !(scaling.factor > 0.0 && scaling.low_freq_factor < scaling.high_freq_factor)
+        {
+            return Err(Error::Config(format!(
+                "the scaling factor ({}) must be positive, and low_freq_factor ({}) less than \
+                 high_freq_factor ({})",
+                scaling.factor, scaling.low_freq_factor, scaling.high_freq_factor
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub fn init<B: Backend>(&self, head_dim: usize, device: &B::Device) -> Result<Rope<B>> {
+        self.validate(head_dim)?;
+
+        let inv_freq: Vec<f32> = self
+            .inv_freq(head_dim)
+            .into_iter()
+            .map(|f| f as f32)
+            .collect();
+        Ok(Rope {
+            inv_freq: Tensor::from_data(TensorData::new(inv_freq, [head_dim / 2]), device),
+        })
+    }
+
+    /// The angle each pair of dimensions turns per position.
+    fn inv_freq(&self, head_dim: usize) -> Vec<f64> {
+        (0..head_dim / 2)
+            .map(|i| {
+                let inv_freq = self.theta_base.powf(-((2 * i) as f64) / head_dim as f64);
+                match &self.scaling {
+                    Some(scaling) => scaling.scale(inv_freq),
+                    None => inv_freq,
+                }
+            })
+            .collect()
+    }
+}
+
+impl RopeScaling {
+    fn scale(&self, inv_freq: f64) -> f64 {
+        let original = self.original_context_length as f64;
+        let wavelength = 2.0 * PI / inv_freq;
+
+        if wavelength < original / self.high_freq_factor {
+            inv_freq
+        } else if wavelength > original / self.low_freq_factor {
+            inv_freq / self.factor
+        } else {
+            let smooth = (original / wavelength - self.low_freq_factor)
+                / (self.high_freq_factor - self.low_freq_factor);
+            (1.0 - smooth) * inv_freq / self.factor + smooth * inv_freq
+        }
+    }
+}
+
+#[derive(Module, Debug)]
+pub struct Rope<B: Backend> {
+    /// `[head_dim / 2]`.
+    inv_freq: Tensor<B, 1>,
+}
+
+impl<B: Backend> Rope<B> {
+    /// Rotates `[batch, num_heads, tokens, head_dim]` queries or keys, the first of which is at
+    /// position `start`. Dimension `i` is paired with `i + head_dim / 2`, as in Llama's Hugging
+    /// Face weights.
+    pub fn forward(&self, x: Tensor<B, 4>, start: usize) -> Tensor<B, 4> {
+        let [_, _, tokens, head_dim] = x.dims();
+        let half = head_dim / 2;
+        let device = x.device();
+
+        let positions = Tensor::<B, 1, Int>::arange(start as i64..(start + tokens) as i64, &device)
+            .float()
+            .reshape([tokens, 1]);
+        let angles = positions.matmul(self.inv_freq.clone().reshape([1, half]));
+        let angles = Tensor::cat(vec![angles.clone(), angles], 1).unsqueeze::<4>();
+
+        let rotated = Tensor::cat(
+            vec![
+                x.clone().slice(s![.., .., .., half..]).neg(),
+                x.clone().slice(s![.., .., .., ..half]),
+            ],
+            3,
+        );
+        x * angles.clone().cos() + rotated * angles.sin()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::{
+        Tensor,
+        backend::{NdArray, ndarray::NdArrayDevice},
+        tensor::{Distribution, Tolerance},
+    };
+
+    use crate::{
+        error::Error,
+        listings::ch03::rope::{RopeConfig, RopeScaling},
+    };
+
+    #[test]
+    fn test_inv_freq() {
+        let inv_freq = RopeConfig::new().inv_freq(4);
+        assert_eq!(inv_freq, [1.0, 0.01]);
+
+        // Llama 3's: the highest frequencies are kept, the lowest divided by the factor, and
+        // those in between interpolated.
+        let config = RopeConfig::new().with_theta_base(500000.0);
+        let scaled = config
+            .clone()
+            .with_scaling(Some(RopeScaling::new()))
+            .inv_freq(128);
+        let unscaled = config.inv_freq(128);
+        assert_eq!(scaled[0], unscaled[0]);
+        assert_eq!(scaled[63], unscaled[63] / 8.0);
+        let ratios: Vec<f64> = scaled.iter().zip(&unscaled).map(|(s, u)| u / s).collect();
+        assert!(ratios.windows(2).all(|w| w[0] <= w[1]));
+        assert!(ratios.iter().any(|r| *r > 1.0 && *r < 8.0));
+
+        assert!(matches!(
+            RopeConfig::new().validate(5),
+            Err(Error::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_rope() {
+        let device = &NdArrayDevice::Cpu;
+        let rope = RopeConfig::new().init::<NdArray>(8, device).unwrap();
+        let q = Tensor::<NdArray, 4>::random([1, 2, 1, 8], Distribution::Default, device);
+        let k = Tensor::<NdArray, 4>::random([1, 2, 1, 8], Distribution::Default, device);
+
+        // The first position isn't rotated, and rotations keep lengths.
+        rope.forward(q.clone(), 0)
+            .into_data()
+            .assert_approx_eq(&q.to_data(), Tolerance::<f32>::default());
+        let norm = |x: Tensor<NdArray, 4>| x.powi_scalar(2).sum().into_scalar();
+        assert!((norm(rope.forward(q.clone(), 7)) - norm(q.clone())).abs() < 1e-5);
+
+        // Scores only depend on the distance between the query and the key.
+        let score = |m: usize, n: usize| {
+            (rope.forward(q.clone(), m) * rope.forward(k.clone(), n))
+                .sum()
+                .into_scalar()
+        };
+        assert!((score(3, 1) - score(10, 8)).abs() < 1e-5);
+        assert!((score(3, 1) - score(3, 2)).abs() > 1e-5);
+    }
+}
@@ -0,0 +1,88 @@
+use burn::config::Config;
+
+use crate::error::{Error, Result};
+
+/// The hyperparameters of a GPT model. The defaults are the book's `GPT_CONFIG_124M`, i.e. the
+/// smallest GPT-2.
+#[derive(Config, Debug)]
+pub struct GPTConfig {
+    #[config(default = 50257)]
+    pub vocab_size: usize,
+    /// The most tokens the model can attend over at once.
+    #[config(default = 1024)]
+    pub context_length: usize,
+    #[config(default = 768)]
+    pub emb_dim: usize,
+    #[config(default = 12)]
+    pub n_heads: usize,
+    #[config(default = 12)]
+    pub n_layers: usize,
+    #[config(default = 0.1)]
+    pub drop_rate: f64,
+    #[config(default = false)]
+    pub qkv_bias: bool,
+}
+
+impl GPTConfig {
+    /// Checks that the hyperparameters describe a model that can actually be built.
+    pub fn validate(&self) -> Result<()> {
+        if self.vocab_size == 0 || self.context_length == 0 || self.n_heads == 0 {
+            return Err(Error::Config(
+                "vocab_size, context_length and n_heads must be positive".to_string(),
+            ));
+        }
+        if !self.emb_dim.is_multiple_of(self.n_heads) {
+            return Err(Error::Config(format!(
+                "emb_dim ({}) must be divisible by n_heads ({})",
+                self.emb_dim, self.n_heads
+            )));
+        }
+        if !(0.0..1.0).contains(&self.drop_rate) {
+            return Err(Error::Config(format!(
+                "drop_rate ({}) must be in [0, 1)",
+                self.drop_rate
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::config::Config;
+    use tempfile::TempDir;
+
+    use crate::{error::Error, listings::ch04::GPTConfig};
+
+    #[test]
+    fn test_gpt_config_124m() {
+        let config = GPTConfig::new();
+
+        assert_eq!(config.vocab_size, 50257);
+        assert_eq!(config.context_length, 1024);
+        assert_eq!(config.emb_dim, 768);
+        assert_eq!(config.n_heads, 12);
+        assert_eq!(config.n_layers, 12);
+        assert_eq!(config.drop_rate, 0.1);
+        assert!(!config.qkv_bias);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn test_gpt_config_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.json");
+
+        GPTConfig::new()
+            .with_context_length(256)
+            .save(&path)
+            .unwrap();
+        assert_eq!(GPTConfig::load(&path).unwrap().context_length, 256);
+
+        assert!(matches!(
+            GPTConfig::new().with_n_heads(5).validate(),
+            Err(Error::Config(_))
+        ));
+    }
+}
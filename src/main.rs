@@ -1,9 +1,14 @@
-use std::{collections::HashMap, error::Error, sync::LazyLock};
+use std::{collections::HashMap, error::Error, path::PathBuf, sync::LazyLock};
 
 use clap::{Parser, Subcommand};
 use llms_from_scratch_burn::{
     Listing,
-    listings::ch02::{E2_1, L2_1},
+    listings::ch02::{
+        E2_1, L2_1, Tokenizer,
+        corpus::Corpus,
+        stats::corpus_stats,
+        tokenizers::{Encoding, SpecialTokenPolicy, TiktokenTokenizer, load_tokenizer},
+    },
 };
 use log::info;
 
@@ -43,6 +48,24 @@ enum Commands {
         /// The listing to run
         id: String,
     },
+    /// Print statistics about a corpus as JSON
+    Stats {
+        /// A file, directory, glob pattern or URL, or `-` for stdin
+        corpus: String,
+        /// Read the corpus as JSON Lines, one document per line, from this field
+        #[arg(long)]
+        jsonl_field: Option<String>,
+        /// A saved tokenizer to encode with, instead of GPT-2's
+        #[arg(long)]
+        tokenizer: Option<PathBuf>,
+        #[arg(long, default_value_t = 256)]
+        max_length: usize,
+        #[arg(long, default_value_t = 128)]
+        stride: usize,
+        /// How many of the most frequent tokens to list
+        #[arg(long, default_value_t = 20)]
+        top: usize,
+    },
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -65,6 +88,32 @@ fn main() -> Result<(), Box<dyn Error>> {
             let listing = LISTINGS.get(id.as_str()).unwrap();
             listing.main()?;
         }
+        Commands::Stats {
+            corpus,
+            jsonl_field,
+            tokenizer,
+            max_length,
+            stride,
+            top,
+        } => {
+            let corpus = match jsonl_field {
+                Some(field) => Corpus::Jsonl {
+                    path: corpus.into(),
+                    field,
+                },
+                None => Corpus::from_arg(&corpus),
+            };
+            let tokenizer: Box<dyn Tokenizer> = match tokenizer {
+                Some(path) => load_tokenizer(&path)?,
+                None => Box::new(TiktokenTokenizer::new(
+                    Encoding::Gpt2,
+                    SpecialTokenPolicy::allow(["<|endoftext|>"]),
+                )?),
+            };
+
+            let stats = corpus_stats(&corpus, tokenizer.as_ref(), max_length, stride, top)?;
+            println!("{}", serde_json::to_string_pretty(&stats)?);
+        }
     }
     Ok(())
 }